@@ -1,17 +1,249 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::{self, BufRead};
-use crate::Cmd::{Addx, Noop};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use crate::Status::{Processing, WaitingCmd};
 
+// Register the CRT and the signal strength are based on
+const X: &str = "x";
+
+// Named registers, created on first write. Unknown registers read as 0,
+// except x that starts at 1.
+#[derive(Debug, Clone)]
+struct Registers {
+    values: BTreeMap<String, i32>,
+}
+
+impl Registers {
+    fn new() -> Self {
+        Self { values: BTreeMap::from([(X.to_string(), 1)]) }
+    }
+
+    fn get(&self, name: &str) -> i32 {
+        self.values.get(name).copied().unwrap_or(0)
+    }
+
+    fn set(&mut self, name: &str, val: i32) {
+        self.values.insert(name.to_string(), val);
+    }
+}
+
+impl Display for Registers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let regs: Vec<String> = self.values.iter().map(|(name, val)| format!("{name}={val}")).collect();
+        write!(f, "{}", regs.join(" "))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum OperandKind {
+    Register,
+    Immediate,
+    // Either a register or an immediate
+    Value,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Reg(String),
+    Imm(i32),
+}
+
+impl Operand {
+    fn value(&self, regs: &Registers) -> i32 {
+        match self {
+            Operand::Reg(name) => regs.get(name),
+            Operand::Imm(val) => *val,
+        }
+    }
+
+    fn reg(&self) -> &str {
+        match self {
+            Operand::Reg(name) => name,
+            Operand::Imm(_) => panic!("Not a register"),
+        }
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Reg(name) => write!(f, "{name}"),
+            Operand::Imm(val) => write!(f, "{val}"),
+        }
+    }
+}
+
+// Arithmetic result out of the range of a register
+#[derive(Debug)]
+struct Overflow;
+
+// Applied when an instruction completes; returns the offset to jump by, if
+// the instruction jumps
+type Effect = fn(&[Operand], &mut Registers) -> Result<Option<i32>, Overflow>;
+
+struct Opcode {
+    name: &'static str,
+    operands: &'static [OperandKind],
+    cycles: u32,
+    effect: Effect,
+}
+
+// Sets register name to op(name, val), where op is a checked operation
+fn update(regs: &mut Registers, name: &str, val: i32, op: fn(i32, i32) -> Option<i32>) -> Result<Option<i32>, Overflow> {
+    let res = op(regs.get(name), val).ok_or(Overflow)?;
+    regs.set(name, res);
+    Ok(None)
+}
+
+// Jumps by the second operand if the first one satisfies cond
+fn jump_if(ops: &[Operand], regs: &Registers, cond: fn(i32) -> bool) -> Result<Option<i32>, Overflow> {
+    if cond(ops[0].value(regs)) {
+        Ok(Some(ops[1].value(regs)))
+    } else {
+        Ok(None)
+    }
+}
+
+const INSTRUCTION_SET: &[Opcode] = &[
+    Opcode { name: "noop", operands: &[], cycles: 1, effect: |_, _| Ok(None) },
+    Opcode {
+        name: "addx",
+        operands: &[OperandKind::Value],
+        cycles: 2,
+        effect: |ops, regs| update(regs, X, ops[0].value(regs), i32::checked_add),
+    },
+    Opcode {
+        name: "addy",
+        operands: &[OperandKind::Value],
+        cycles: 2,
+        effect: |ops, regs| update(regs, "y", ops[0].value(regs), i32::checked_add),
+    },
+    Opcode {
+        name: "mulx",
+        operands: &[OperandKind::Value],
+        cycles: 3,
+        effect: |ops, regs| update(regs, X, ops[0].value(regs), i32::checked_mul),
+    },
+    Opcode {
+        name: "set",
+        operands: &[OperandKind::Register, OperandKind::Value],
+        cycles: 1,
+        effect: |ops, regs| { regs.set(ops[0].reg(), ops[1].value(regs)); Ok(None) },
+    },
+    Opcode {
+        name: "add",
+        operands: &[OperandKind::Register, OperandKind::Value],
+        cycles: 2,
+        effect: |ops, regs| update(regs, ops[0].reg(), ops[1].value(regs), i32::checked_add),
+    },
+    Opcode {
+        name: "mul",
+        operands: &[OperandKind::Register, OperandKind::Value],
+        cycles: 3,
+        effect: |ops, regs| update(regs, ops[0].reg(), ops[1].value(regs), i32::checked_mul),
+    },
+    Opcode { name: "jmp", operands: &[OperandKind::Value], cycles: 1, effect: |ops, regs| Ok(Some(ops[0].value(regs))) },
+    Opcode {
+        name: "jz",
+        operands: &[OperandKind::Value, OperandKind::Value],
+        cycles: 1,
+        effect: |ops, regs| jump_if(ops, regs, |v| v == 0),
+    },
+    Opcode {
+        name: "jnz",
+        operands: &[OperandKind::Value, OperandKind::Value],
+        cycles: 1,
+        effect: |ops, regs| jump_if(ops, regs, |v| v != 0),
+    },
+    Opcode {
+        name: "jgz",
+        operands: &[OperandKind::Value, OperandKind::Value],
+        cycles: 1,
+        effect: |ops, regs| jump_if(ops, regs, |v| v > 0),
+    },
+    Opcode {
+        name: "jlz",
+        operands: &[OperandKind::Value, OperandKind::Value],
+        cycles: 1,
+        effect: |ops, regs| jump_if(ops, regs, |v| v < 0),
+    },
+];
+
+struct Instr {
+    op: &'static Opcode,
+    operands: Vec<Operand>,
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.op.name)?;
+        self.operands.iter().try_for_each(|o| write!(f, " {o}"))
+    }
+}
+
+#[derive(Debug)]
+enum AsmError {
+    UnknownOpcode { line: usize, name: String },
+    OperandCount { line: usize, expected: usize, found: usize },
+    BadOperand { line: usize, operand: String },
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsmError::UnknownOpcode { line, name } => write!(f, "line {}: unknown instruction {}", line, name),
+            AsmError::OperandCount { line, expected, found } =>
+                write!(f, "line {}: expected {} operand(s), found {}", line, expected, found),
+            AsmError::BadOperand { line, operand } => write!(f, "line {}: invalid operand {}", line, operand),
+        }
+    }
+}
+
+fn parse_operand(s: &str, kind: OperandKind) -> Option<Operand> {
+    match s.parse::<i32>() {
+        Ok(val) if kind != OperandKind::Register => Some(Operand::Imm(val)),
+        Ok(_) => None,
+        Err(_) if kind != OperandKind::Immediate && s.chars().all(|c| c.is_ascii_lowercase()) =>
+            Some(Operand::Reg(s.to_string())),
+        Err(_) => None,
+    }
+}
+
+fn parse_instr(s: &str, line: usize) -> Result<Instr, AsmError> {
+    let mut tokens = s.split_whitespace();
+    let name = tokens.next().unwrap_or("");
+    let op = INSTRUCTION_SET.iter().find(|op| op.name == name)
+        .ok_or_else(|| AsmError::UnknownOpcode { line, name: name.to_string() })?;
+    let tokens: Vec<&str> = tokens.collect();
+    if tokens.len() != op.operands.len() {
+        return Err(AsmError::OperandCount { line, expected: op.operands.len(), found: tokens.len() });
+    }
+    let operands = tokens.iter().zip(op.operands)
+        .map(|(t, &kind)| parse_operand(t, kind).ok_or_else(|| AsmError::BadOperand { line, operand: t.to_string() }))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Instr { op, operands })
+}
+
+// An instruction overflowed a register when completing
 #[derive(Debug)]
-enum Cmd {
-    Noop,
-    Addx(i32),
+struct ExecError {
+    cycle: u32,
+    pc: usize,
+    instr: String,
 }
 
+impl Display for ExecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cycle {}: instruction {} ({}) overflows", self.cycle, self.pc, self.instr)
+    }
+}
+
+// Instruction in progress: its index and the cycle at whose start it completes
 #[derive(Debug, Copy, Clone)]
 struct Change {
-    delta: i32,
+    pc: usize,
     end_cycle: u32,
 }
 
@@ -21,103 +253,512 @@ enum Status {
 }
 
 struct Device {
-    val: i32,
+    program: Vec<Instr>,
+    regs: Registers,
+    pc: usize,
     cycle: u32,
     pending_change: Option<Change>,
 }
 
 impl Device {
-    fn new() -> Self {
+    fn new(program: Vec<Instr>) -> Self {
         Self {
-            val: 1,
+            program,
+            regs: Registers::new(),
+            pc: 0,
             cycle: 0,
             pending_change: None,
         }
     }
 
-    // returns true if the simulation is finished
-    fn clock_tick(&mut self) -> Status {
+    fn clock_tick(&mut self) -> Result<Status, ExecError> {
         self.cycle += 1;
         match self.pending_change {
-            None => { WaitingCmd },
-            Some(Change{delta, end_cycle}) => {
+            None => { Ok(WaitingCmd) },
+            Some(Change{pc, end_cycle}) => {
                 if self.cycle == end_cycle {
-                    self.val += delta;
+                    let instr = &self.program[pc];
+                    let jump = (instr.op.effect)(&instr.operands, &mut self.regs)
+                        .map_err(|_| ExecError { cycle: self.cycle, pc, instr: instr.to_string() })?;
+                    self.pc = match jump {
+                        // Jumping outside of the program halts it
+                        Some(offset) => usize::try_from(pc as i64 + offset as i64).unwrap_or(usize::MAX),
+                        None => pc + 1,
+                    };
                     self.pending_change = None;
-                    WaitingCmd
+                    Ok(WaitingCmd)
                 } else {
-                    Processing
+                    Ok(Processing)
                 }
             }
         }
     }
 
-    fn submit_command(&mut self, cmd: &Cmd) {
-        self.pending_change = match *cmd {
-            Noop => Some(Change {
-                delta: 0,
-                end_cycle: self.cycle + 1,
-            }),
-            Addx(delta) => Some(Change {
-                delta,
-                end_cycle: self.cycle + 2,
-            })
+    // Starts the instruction at pc, if the program has not ended
+    fn submit_command(&mut self) {
+        self.pending_change = self.program.get(self.pc).map(|instr| Change {
+            pc: self.pc,
+            end_cycle: self.cycle + instr.op.cycles,
+        });
+    }
+
+    // Runs a whole cycle, returning the index of the instruction it started, if any
+    fn run_cycle(&mut self) -> Result<Option<usize>, ExecError> {
+        match self.clock_tick()? {
+            WaitingCmd => {
+                self.submit_command();
+                Ok(self.pending_change.map(|c| c.pc))
+            },
+            Processing => Ok(None),
+        }
+    }
+
+    fn describe_pending(&self) -> String {
+        self.pending_change.map_or("-".to_string(), |c| self.program[c.pc].to_string())
+    }
+}
+
+const SCREEN_WIDTH: usize = 40;
+const SCREEN_HEIGHT: usize = 6;
+const SPRITE_WIDTH: usize = 3;
+
+// Pixels lit by the beam, row by row
+struct Crt {
+    width: usize,
+    height: usize,
+    sprite_width: usize,
+    pixels: Vec<bool>,
+}
+
+impl Crt {
+    fn new(width: usize, height: usize, sprite_width: usize) -> Self {
+        assert!(width > 0 && height > 0, "Empty screen");
+        Self { width, height, sprite_width, pixels: vec![false; width * height] }
+    }
+
+    // Draws the pixel under the beam during cycle, given the sprite position.
+    // The sprite is centered on its position; even widths have the extra
+    // column on the right.
+    fn draw(&mut self, cycle: i32, sprite: i32) {
+        let idx = (cycle - 1) as usize;
+        if idx < self.pixels.len() {
+            let pos = (idx % self.width) as i32;
+            let left = sprite - (self.sprite_width as i32 - 1) / 2;
+            self.pixels[idx] = left <= pos && pos < left + self.sprite_width as i32;
+        }
+    }
+
+    fn pixel(&self, row: usize, col: usize) -> bool {
+        self.pixels[row * self.width + col]
+    }
+
+    fn render(&self) -> Vec<String> {
+        self.pixels.chunks(self.width)
+            .map(|row| row.iter().map(|&lit| if lit { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    // Binary PBM, every pixel drawn as a scale x scale square
+    fn write_pbm(&self, path: &str, scale: usize) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "P4\n{} {}\n", self.width * scale, self.height * scale)?;
+        for row in self.pixels.chunks(self.width) {
+            // Rows are padded to a whole byte, with the first pixel in the highest bit
+            let mut bytes = vec![0u8; (self.width * scale).div_ceil(8)];
+            row.iter().flat_map(|&lit| std::iter::repeat_n(lit, scale)).enumerate()
+                .filter(|(_, lit)| *lit)
+                .for_each(|(col, _)| bytes[col / 8] |= 0x80 >> (col % 8));
+            for _ in 0..scale {
+                out.write_all(&bytes)?;
+            }
+        }
+        out.flush()
+    }
+}
+
+// Cycles during which the signal strength is sampled
+enum Schedule {
+    // first, first + period, first + 2 * period...
+    Periodic { first: u32, period: u32 },
+    Cycles(Vec<u32>),
+}
+
+impl Schedule {
+    // FIRST:PERIOD, or a list of cycles such as 20,60,100
+    fn parse(s: &str) -> Schedule {
+        match s.split_once(':') {
+            Some((first, period)) => Schedule::Periodic {
+                first: first.parse::<u32>().expect("Invalid first sample"),
+                period: period.parse::<u32>().ok().filter(|&p| p > 0).expect("Invalid sampling period"),
+            },
+            None => Schedule::Cycles(s.split(',').map(|c| c.parse::<u32>().expect("Invalid sample cycle")).collect()),
+        }
+    }
+
+    fn samples(&self, cycle: u32) -> bool {
+        match self {
+            Schedule::Periodic { first, period } => cycle >= *first && (cycle - first).is_multiple_of(*period),
+            Schedule::Cycles(cycles) => cycles.contains(&cycle),
+        }
+    }
+}
+
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+// Glyphs are separated by a blank column
+const GLYPH_SPACING: usize = 1;
+
+// Capital letters of the 4x6 font the puzzles draw with
+const FONT: &[(char, [&str; GLYPH_HEIGHT])] = &[
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+#[derive(Debug)]
+enum OcrError {
+    BadHeight { height: usize },
+    // Position (from 1) and rows of a glyph missing from the font
+    UnknownGlyph { index: usize, rows: Vec<String> },
+}
+
+impl Display for OcrError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OcrError::BadHeight { height } =>
+                write!(f, "screen is {} pixel(s) high, letters are {}", height, GLYPH_HEIGHT),
+            OcrError::UnknownGlyph { index, rows } =>
+                write!(f, "unknown glyph at position {}: {}", index, rows.join("/")),
         }
     }
 }
 
-fn parse_input() -> Vec<Cmd> {
+// Reads the letters drawn on the screen, left to right
+fn ocr(crt: &Crt) -> Result<String, OcrError> {
+    if crt.height != GLYPH_HEIGHT {
+        return Err(OcrError::BadHeight { height: crt.height });
+    }
+    (0..crt.width).step_by(GLYPH_WIDTH + GLYPH_SPACING)
+        .filter(|&left| left + GLYPH_WIDTH <= crt.width)
+        .enumerate()
+        .map(|(idx, left)| {
+            let rows: Vec<String> = (0..GLYPH_HEIGHT).map(|row| {
+                (left..left + GLYPH_WIDTH).map(|col| if crt.pixel(row, col) { '#' } else { '.' }).collect()
+            }).collect();
+            FONT.iter().find(|(_, glyph)| glyph.iter().zip(&rows).all(|(g, r)| g == r))
+                .map(|(c, _)| *c)
+                .ok_or(OcrError::UnknownGlyph { index: idx + 1, rows })
+        })
+        .collect()
+}
+
+fn parse_input() -> Result<Vec<Instr>, AsmError> {
     let f = File::open("input/input.txt").unwrap();
     let lines = io::BufReader::new(f).lines();
-    lines.map(|line| {
-        let line = line.unwrap();
-        match &line[0..4] {
-            "noop" => Noop,
-            "addx" => {
-                let val = &line[5..].parse::<i32>().unwrap();
-                Addx(*val)
+    lines.enumerate()
+        .map(|(idx, line)| parse_instr(&line.unwrap(), idx + 1))
+        .collect()
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    fn parse(s: &str) -> Option<Comparison> {
+        match s {
+            "<" => Some(Comparison::Lt),
+            "<=" => Some(Comparison::Le),
+            ">" => Some(Comparison::Gt),
+            ">=" => Some(Comparison::Ge),
+            "==" => Some(Comparison::Eq),
+            "!=" => Some(Comparison::Ne),
+            _ => None,
+        }
+    }
+
+    fn eval(&self, a: i32, b: i32) -> bool {
+        match self {
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Gt => a > b,
+            Comparison::Ge => a >= b,
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+        }
+    }
+}
+
+// Register condition, such as x > 10. It triggers when it becomes true.
+struct Watch {
+    text: String,
+    reg: String,
+    cmp: Comparison,
+    operand: Operand,
+    holds: bool,
+}
+
+impl Watch {
+    fn eval(&self, regs: &Registers) -> bool {
+        self.cmp.eval(regs.get(&self.reg), self.operand.value(regs))
+    }
+}
+
+enum Breakpoint {
+    Cycle(u32),
+    // Triggers when the instruction with the given index starts
+    Instr(usize),
+}
+
+struct Debugger {
+    device: Device,
+    crt: Crt,
+    limit: u32,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    // Set once an instruction overflows: the program cannot go on
+    fault: bool,
+}
+
+impl Debugger {
+    fn new(device: Device, crt: Crt, limit: u32) -> Self {
+        Self { device, crt, limit, breakpoints: Vec::new(), watches: Vec::new(), fault: false }
+    }
+
+    fn finished(&self) -> bool {
+        self.fault || self.device.cycle >= self.limit
+    }
+
+    // Runs a cycle, returning why the execution should stop there, if it should
+    fn step(&mut self) -> Option<String> {
+        let started = match self.device.run_cycle() {
+            Ok(started) => started,
+            Err(e) => {
+                self.fault = true;
+                return Some(format!("fault at {e}"));
+            }
+        };
+        let cycle = self.device.cycle;
+        self.crt.draw(cycle as i32, self.device.regs.get(X));
+
+        let mut reasons = Vec::new();
+        for b in &self.breakpoints {
+            match *b {
+                Breakpoint::Cycle(c) if c == cycle => reasons.push(format!("breakpoint at cycle {c}")),
+                Breakpoint::Instr(pc) if started == Some(pc) => reasons.push(format!("breakpoint at instruction {pc}")),
+                _ => {}
+            }
+        }
+        for w in self.watches.iter_mut() {
+            let holds = w.eval(&self.device.regs);
+            if holds && !w.holds {
+                reasons.push(format!("watch {}", w.text));
+            }
+            w.holds = holds;
+        }
+        if reasons.is_empty() { None } else { Some(reasons.join(", ")) }
+    }
+
+    // Runs up to count cycles, or until a breakpoint or a watch triggers
+    fn run(&mut self, count: Option<u32>) {
+        let mut done = 0;
+        while !self.finished() && count.is_none_or(|count| done < count) {
+            done += 1;
+            if let Some(reason) = self.step() {
+                println!("Stopped: {reason}");
+                break;
             }
-            _ => panic!("Unknown command")
         }
-    }).collect()
+        if self.device.cycle >= self.limit {
+            println!("Reached the cycle limit ({})", self.limit);
+        }
+    }
+
+    fn print_state(&self) {
+        let d = &self.device;
+        println!("Cycle {}, pc {}, {}", d.cycle, d.pc, d.regs);
+        match d.pending_change {
+            Some(c) => println!("  pending: {} (instruction {}), completes at cycle {}", d.program[c.pc], c.pc, c.end_cycle),
+            None => println!("  pending: -"),
+        }
+        match d.cycle.checked_sub(1).map(|idx| idx as usize) {
+            Some(idx) if idx < self.crt.pixels.len() =>
+                println!("  beam: row {}, column {}", idx / self.crt.width, idx % self.crt.width),
+            Some(_) => println!("  beam: off screen"),
+            None => println!("  beam: not started"),
+        }
+    }
+
+    fn add_watch(&mut self, args: &[&str]) -> Result<(), String> {
+        let usage = || "Usage: watch REG OP VALUE".to_string();
+        let [reg, cmp, val] = args else { return Err(usage()) };
+        let cmp = Comparison::parse(cmp).ok_or_else(usage)?;
+        let operand = parse_operand(val, OperandKind::Value).ok_or_else(usage)?;
+        let mut watch = Watch { text: args.join(" "), reg: reg.to_string(), cmp, operand, holds: false };
+        watch.holds = watch.eval(&self.device.regs);
+        self.watches.push(watch);
+        Ok(())
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let usage = || "Usage: break cycle|instr N".to_string();
+        let [kind, n] = args else { return Err(usage()) };
+        let breakpoint = match (*kind, n.parse::<usize>()) {
+            ("cycle", Ok(n)) => Breakpoint::Cycle(n as u32),
+            ("instr", Ok(n)) => Breakpoint::Instr(n),
+            _ => return Err(usage()),
+        };
+        self.breakpoints.push(breakpoint);
+        Ok(())
+    }
+
+    // Reads commands until quit or the end of the input:
+    //   break cycle|instr N, watch REG OP VALUE, delete, step [N], continue,
+    //   print, screen, quit
+    // An empty line steps a single cycle.
+    fn command_loop(&mut self, input: impl BufRead) {
+        self.print_state();
+        for line in input.lines() {
+            let line = line.unwrap();
+            let split: Vec<&str> = line.split_whitespace().collect();
+            let result = match split.first().copied().unwrap_or("s") {
+                "b" | "break" => self.add_breakpoint(&split[1..]),
+                "w" | "watch" => self.add_watch(&split[1..]),
+                "d" | "delete" => {
+                    self.breakpoints.clear();
+                    self.watches.clear();
+                    Ok(())
+                },
+                "s" | "step" => match split.get(1).map_or(Ok(1), |n| n.parse::<u32>()) {
+                    Ok(n) => {
+                        self.run(Some(n));
+                        self.print_state();
+                        Ok(())
+                    },
+                    Err(_) => Err("Usage: step [N]".to_string()),
+                },
+                "c" | "continue" => {
+                    self.run(None);
+                    self.print_state();
+                    Ok(())
+                },
+                "p" | "print" => {
+                    self.print_state();
+                    Ok(())
+                },
+                "screen" => {
+                    self.crt.render().iter().for_each(|row| println!("{row}"));
+                    Ok(())
+                },
+                "q" | "quit" => break,
+                cmd => Err(format!("Unknown command {cmd}")),
+            };
+            if let Err(e) = result {
+                eprintln!("{e}");
+            }
+        }
+    }
 }
 
+// Usage: day_10 [--screen WxH] [--sprite N] [--sample FIRST:PERIOD|C,C...] [--cycles N]
+//               [--pbm FILE [SCALE]] [--trace] [--debug [SCRIPT]]
+// --screen and --sprite default to 40x6 and 3, --sample to 20:40
+// --cycles sets how many cycles to run, one per pixel by default
+// --trace prints the registers during every cycle
+// --debug starts the debugger, reading commands from SCRIPT or stdin
 fn main() {
-    let input = parse_input();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let arg_value = |name: &str| {
+        args.iter().position(|a| a == name).map(|idx| args.get(idx + 1).expect("Missing argument value"))
+    };
+    let program = match parse_input() {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return;
+        }
+    };
 
-    let mut input_it = input.iter();
-    let mut device = Device::new();
+    let (width, height) = arg_value("--screen").map_or((SCREEN_WIDTH, SCREEN_HEIGHT), |s| {
+        let (w, h) = s.split_once('x').expect("Invalid screen size");
+        (w.parse::<usize>().expect("Invalid screen width"), h.parse::<usize>().expect("Invalid screen height"))
+    });
+    let sprite_width = arg_value("--sprite").map_or(SPRITE_WIDTH, |n| n.parse::<usize>().expect("Invalid sprite width"));
+    let mut crt = Crt::new(width, height, sprite_width);
+    let schedule = arg_value("--sample").map_or(Schedule::Periodic { first: 20, period: 40 }, |s| Schedule::parse(s));
 
-    let values: Vec<_> = (1..241).map(|cycle| {
-        match device.clock_tick() {
-            WaitingCmd => {
-                if let Some(cmd) = input_it.next() {
-                    device.submit_command(cmd);
-                }
-            },
-            Processing => { }
+    let mut device = Device::new(program);
+    let limit = arg_value("--cycles").map_or((width * height) as u32, |n| n.parse::<u32>().expect("Invalid cycle count"));
+    let trace = args.iter().any(|a| a == "--trace");
+
+    if let Some(idx) = args.iter().position(|a| a == "--debug") {
+        let mut debugger = Debugger::new(device, crt, limit);
+        match args.get(idx + 1).filter(|a| !a.starts_with("--")) {
+            Some(script) => debugger.command_loop(BufReader::new(File::open(script).unwrap())),
+            None => debugger.command_loop(io::stdin().lock()),
         }
-        (cycle, device.val)
-    }).collect();
+        return;
+    }
+
+    let mut values = Vec::new();
+    for cycle in 1..=limit as i32 {
+        if let Err(e) = device.run_cycle() {
+            eprintln!("Error: {e}");
+            return;
+        }
+        if trace {
+            println!("cycle {:>3}: {:<12} {}", cycle, device.describe_pending(), device.regs);
+        }
+        values.push((cycle, device.regs.get(X)));
+    }
 
     // Part 1
+    // A single strength always fits an i64, their sum may not
     let signal_strength = values.iter()
-        .skip(19)
-        .step_by(40)
-        .map(|(cycle, val)| cycle*val)
-        .sum::<i32>();
-    println!("Signal strength: {}", signal_strength);
+        .filter(|(cycle, _)| schedule.samples(*cycle as u32))
+        .try_fold(0i64, |sum, &(cycle, val)| sum.checked_add(cycle as i64 * val as i64));
+    match signal_strength {
+        Some(signal_strength) => println!("Signal strength: {}", signal_strength),
+        None => eprintln!("Error: signal strength overflows"),
+    }
 
     // Part 2
-    values.iter().for_each(|(cycle, val)| {
-        let pos = (*cycle-1)%40;
-        if val.abs_diff(pos) <= 1 {
-            print!("#");
-        } else {
-            print!(".")
+    values.iter().for_each(|&(cycle, val)| crt.draw(cycle, val));
+    crt.render().iter().for_each(|row| println!("{row}"));
+    // Only screens as high as the font can hold letters
+    if crt.height == GLYPH_HEIGHT {
+        match ocr(&crt) {
+            Ok(letters) => println!("Letters: {letters}"),
+            Err(e) => eprintln!("Error: {e}"),
         }
-        if pos==39 {
-            println!();
+    }
+
+    if let Some(idx) = args.iter().position(|a| a == "--pbm") {
+        let path = args.get(idx + 1).expect("Missing PBM file");
+        let scale = args.get(idx + 2).and_then(|s| s.parse::<usize>().ok()).unwrap_or(1);
+        match crt.write_pbm(path, scale) {
+            Ok(()) => println!("Screen written to {path}"),
+            Err(e) => eprintln!("Error: {e}"),
         }
-    });
-}
\ No newline at end of file
+    }
+}
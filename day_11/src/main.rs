@@ -1,32 +1,275 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::env;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Lines};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::iter::Peekable;
+use std::str::Chars;
 use itertools::Itertools;
-use crate::OP::{ADD, MUL, SQUARE};
 
-#[derive(Debug)]
-enum OP {
-    ADD(usize),
-    MUL(usize),
-    SQUARE
+// Worry levels are signed, so that operations may subtract, and kept exact:
+// any overflow stops the simulation
+type Worry = i128;
+
+// Why an operation could not compute a worry level
+#[derive(Debug, Copy, Clone)]
+enum WorryError {
+    DivisionByZero,
+    Overflow,
+}
+
+impl Display for WorryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorryError::DivisionByZero => write!(f, "division by zero"),
+            WorryError::Overflow => write!(f, "worry level overflow"),
+        }
+    }
 }
 
+// A WorryError, with the monkey and the round (from 1) it happened in
 #[derive(Debug)]
+struct RoundError {
+    round: usize,
+    monkey: usize,
+    error: WorryError,
+}
+
+impl Display for RoundError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "round {}, monkey {}: {}", self.round, self.monkey, self.error)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+// Right-hand side of an "Operation: new = ..." line
+#[derive(Debug, Clone)]
+enum Expr {
+    Old,
+    Const(Worry),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+}
+
+impl Expr {
+    // Parses +, -, *, / with the usual precedence, parentheses, constants and old
+    fn parse(s: &str) -> Expr {
+        let mut chars = s.chars().peekable();
+        let expr = Expr::parse_sum(&mut chars);
+        skip_spaces(&mut chars);
+        if let Some(c) = chars.next() {
+            panic!("Unexpected {c} in operation {s}");
+        }
+        expr
+    }
+
+    fn parse_sum(chars: &mut Peekable<Chars>) -> Expr {
+        let mut expr = Expr::parse_product(chars);
+        loop {
+            skip_spaces(chars);
+            let op = match chars.peek() {
+                Some('+') => BinOp::Add,
+                Some('-') => BinOp::Sub,
+                _ => return expr,
+            };
+            chars.next();
+            expr = Expr::Binary(Box::new(expr), op, Box::new(Expr::parse_product(chars)));
+        }
+    }
+
+    fn parse_product(chars: &mut Peekable<Chars>) -> Expr {
+        let mut expr = Expr::parse_factor(chars);
+        loop {
+            skip_spaces(chars);
+            let op = match chars.peek() {
+                Some('*') => BinOp::Mul,
+                Some('/') => BinOp::Div,
+                _ => return expr,
+            };
+            chars.next();
+            expr = Expr::Binary(Box::new(expr), op, Box::new(Expr::parse_factor(chars)));
+        }
+    }
+
+    fn parse_factor(chars: &mut Peekable<Chars>) -> Expr {
+        skip_spaces(chars);
+        match chars.peek() {
+            Some('(') => {
+                chars.next();
+                let expr = Expr::parse_sum(chars);
+                skip_spaces(chars);
+                if chars.next() != Some(')') {
+                    panic!("Missing closing parenthesis");
+                }
+                expr
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let mut val: Worry = 0;
+                while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    val = val.checked_mul(10).and_then(|v| v.checked_add(d as Worry)).expect("Constant too large");
+                    chars.next();
+                }
+                Expr::Const(val)
+            },
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                    name.push(c);
+                    chars.next();
+                }
+                match name.as_str() {
+                    "old" => Expr::Old,
+                    _ => panic!("Unknown variable {name}"),
+                }
+            },
+            c => panic!("Unexpected {:?} in operation", c),
+        }
+    }
+
+    // True if the expression only adds, subtracts and multiplies, so it can
+    // be computed modulo any number
+    fn is_polynomial(&self) -> bool {
+        match self {
+            Expr::Old | Expr::Const(_) => true,
+            Expr::Binary(_, BinOp::Div, _) => false,
+            Expr::Binary(a, _, b) => a.is_polynomial() && b.is_polynomial(),
+        }
+    }
+
+    // Evaluates the expression, modulo the given number if any. Reduced values
+    // are the non-negative residues, which a negative exact value maps to as
+    // well, so both agree on every divisibility test. Divisions round down.
+    fn eval(&self, old: Worry, modulo: Option<Worry>) -> Result<Worry, WorryError> {
+        let reduce = |val: Worry| modulo.map_or(val, |m| val.rem_euclid(m));
+        match self {
+            Expr::Old => Ok(reduce(old)),
+            Expr::Const(val) => Ok(reduce(*val)),
+            Expr::Binary(a, op, b) => {
+                let (a, b) = (a.eval(old, modulo)?, b.eval(old, modulo)?);
+                match op {
+                    BinOp::Add => a.checked_add(b).map(reduce).ok_or(WorryError::Overflow),
+                    BinOp::Sub => a.checked_sub(b).map(reduce).ok_or(WorryError::Overflow),
+                    BinOp::Mul => a.checked_mul(b).map(reduce).ok_or(WorryError::Overflow),
+                    BinOp::Div if b == 0 => Err(WorryError::DivisionByZero),
+                    BinOp::Div => a.checked_div_euclid(b).ok_or(WorryError::Overflow),
+                }
+            }
+        }
+    }
+}
+
+fn skip_spaces(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+// Condition deciding where an item is thrown
+#[derive(Debug, Copy, Clone)]
+enum Test {
+    DivisibleBy(Worry),
+    GreaterThan(Worry),
+    LessThan(Worry),
+    EqualTo(Worry),
+}
+
+impl Test {
+    fn parse(s: &str) -> Test {
+        let (kind, val) = s.rsplit_once(' ').expect("Malformed test");
+        let val = val.parse::<Worry>().unwrap();
+        match kind {
+            "divisible by" if val > 0 => Test::DivisibleBy(val),
+            "greater than" => Test::GreaterThan(val),
+            "less than" => Test::LessThan(val),
+            "equal to" => Test::EqualTo(val),
+            _ => panic!("Unknown test {s}"),
+        }
+    }
+
+    fn check(&self, item: Worry) -> bool {
+        match *self {
+            Test::DivisibleBy(n) => item.rem_euclid(n) == 0,
+            Test::GreaterThan(n) => item > n,
+            Test::LessThan(n) => item < n,
+            Test::EqualTo(n) => item == n,
+        }
+    }
+}
+
+// Items are numbered from 0, following the starting items of each monkey
+#[derive(Debug, Copy, Clone)]
+struct Item {
+    id: usize,
+    worry: Worry,
+}
+
+type Mailbox = HashMap<usize, Vec<Item>>;
+
+// State of a monkey at the end of a round
+struct RoundRow {
+    round: usize,
+    monkey: usize,
+    inspections: usize,
+    items: Vec<Item>,
+}
+
+// One inspection of an item
+struct LineageStep {
+    round: usize,
+    item: usize,
+    monkey: usize,
+    before: Worry,
+    after: Worry,
+    target: usize,
+}
+
+// What happened during the rounds; lineage is restricted to a single item
+// when item is set
+#[derive(Default)]
+struct Trace {
+    item: Option<usize>,
+    rounds: Vec<RoundRow>,
+    lineage: Vec<LineageStep>,
+}
+
+impl Trace {
+    fn write_rounds_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "round,monkey,inspections,items")?;
+        for r in &self.rounds {
+            let items = r.items.iter().map(|i| format!("{}:{}", i.id, i.worry)).join(" ");
+            writeln!(w, "{},{},{},{}", r.round, r.monkey, r.inspections, items)?;
+        }
+        Ok(())
+    }
+
+    fn write_lineage_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "round,item,monkey,worry_before,worry_after,target")?;
+        for s in &self.lineage {
+            writeln!(w, "{},{},{},{},{},{}", s.round, s.item, s.monkey, s.before, s.after, s.target)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
 struct Monkey {
     monkey_id: usize,
-    items: Vec<usize>,
-    op: OP,
-    test: usize,
+    items: Vec<Item>,
+    op: Expr,
+    test: Test,
     test_true: usize,
     test_false: usize,
     inspections: usize,
 }
 
 impl Monkey {
-    fn parse(lines: &mut Lines<BufReader<File>>) -> Self {
+    fn parse(header: &str, lines: &mut Lines<BufReader<File>>) -> Self {
         // Monkey
-        let next = lines.next().unwrap().unwrap();
-        let mut id_split = next.split_whitespace();
+        let mut id_split = header.split_whitespace();
         let monkey_id = id_split.nth(1).unwrap()
             .replace(":", "").parse::<usize>().unwrap();
 
@@ -35,37 +278,16 @@ impl Monkey {
         let items_split = next.split_whitespace();
         let items = items_split.skip(2).map(|val| {
             let val = val.replace(",", "");
-            val.parse::<usize>().unwrap()
+            Item { id: 0, worry: val.parse::<Worry>().unwrap() }
         }).collect();
 
         // Operation
         let next = lines.next().unwrap().unwrap();
-        let mut op_line_split = next.split_whitespace();
-        let op = match op_line_split.nth(4).unwrap() {
-            "*" => {
-                let val = op_line_split.nth(0).unwrap();
-                if val.eq("old") {
-                    SQUARE
-                } else {
-                    let val = val.parse::<usize>().unwrap();
-                    MUL(val)
-                }
-            },
-            "+" => {
-                let val = op_line_split.nth(0).unwrap();
-                let val = val.parse::<usize>().unwrap();
-                ADD(val)
-            },
-            _ => {
-                panic!("Unknown operation")
-            }
-        };
+        let op = Expr::parse(next.split_once('=').expect("Malformed operation").1);
 
         // Test
         let next = lines.next().unwrap().unwrap();
-        let mut test_split = next.split_whitespace();
-        let test = test_split.nth(3).unwrap();
-        let test = test.parse::<usize>().unwrap();
+        let test = Test::parse(next.trim().strip_prefix("Test: ").expect("Malformed test"));
 
         // Test true
         let next = lines.next().unwrap().unwrap();
@@ -79,9 +301,6 @@ impl Monkey {
         let test_false = test_false_split.nth(5).unwrap();
         let test_false = test_false.parse::<usize>().unwrap();
 
-        // New line
-        lines.next();
-
         Monkey {
             monkey_id,
             items,
@@ -93,52 +312,106 @@ impl Monkey {
         }
     }
 
-    fn receive_messages(&mut self, mailbox: &mut HashMap<usize, Vec<usize>>) {
-        let my_inbox = mailbox.entry(self.monkey_id).or_insert_with(|| Vec::new());
+    fn receive_messages(&mut self, mailbox: &mut Mailbox) {
+        let my_inbox = mailbox.entry(self.monkey_id).or_default();
         my_inbox.iter().for_each(|m| self.items.push(*m));
         my_inbox.clear();
     }
 
-    fn process_round(&mut self, mailbox: &mut HashMap<usize, Vec<usize>>, modulo: usize, div3: bool) {
-        self.items.iter()
-            .map(|item| self.perform_op(*item, modulo))
-            .map(|item| if div3 { item / 3} else { item })
-            .map(|item| (item, self.select_monkey(item)))
-            .for_each(|(item, monkey)| self.sent_to_monkey(monkey, item, mailbox));
-        self.inspections += self.items.len() as usize;
+    fn process_round(&mut self, mailbox: &mut Mailbox, modulo: Option<Worry>, div3: bool,
+                     round: usize, mut trace: Option<&mut Trace>) -> Result<(), RoundError> {
+        let worries = self.items.iter()
+            .map(|item| self.perform_op(item.worry, modulo))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| RoundError { round, monkey: self.monkey_id, error })?;
+        self.items.iter().zip(worries)
+            .map(|(item, worry)| (item, if div3 { worry.div_euclid(3) } else { worry }))
+            .map(|(item, worry)| (Item { id: item.id, worry }, item.worry, self.select_monkey(worry)))
+            .for_each(|(item, before, monkey)| {
+                if let Some(trace) = trace.as_deref_mut().filter(|t| t.item.is_none_or(|id| id == item.id)) {
+                    trace.lineage.push(LineageStep {
+                        round,
+                        item: item.id,
+                        monkey: self.monkey_id,
+                        before,
+                        after: item.worry,
+                        target: monkey,
+                    });
+                }
+                self.sent_to_monkey(monkey, item, mailbox)
+            });
+        self.inspections += self.items.len();
         self.items.clear();
+        Ok(())
     }
 
-    fn perform_op(&self, item: usize, modulo: usize) -> usize {
-        match self.op {
-            ADD(x) => (item + x) % modulo,
-            MUL(x) => (item * x) % modulo,
-            SQUARE => (item * item) % modulo,
-        }
+    fn perform_op(&self, item: Worry, modulo: Option<Worry>) -> Result<Worry, WorryError> {
+        self.op.eval(item, modulo)
     }
 
-    fn select_monkey(&self, item: usize) -> usize {
-        if item % self.test == 0 {
+    fn select_monkey(&self, item: Worry) -> usize {
+        if self.test.check(item) {
             self.test_true
         } else {
             self.test_false
         }
     }
 
-    fn sent_to_monkey(&self, monkey: usize, item: usize, mailbox: &mut HashMap<usize, Vec<usize>>) {
+    fn sent_to_monkey(&self, monkey: usize, item: Item, mailbox: &mut Mailbox) {
         mailbox.entry(monkey)
-            .or_insert_with(|| Vec::new())
+            .or_default()
             .push(item);
     }
 }
 
-fn parse_input() -> Vec<Monkey> {
+#[derive(Debug)]
+enum InputError {
+    NoMonkeys,
+    DuplicateMonkey(usize),
+    UnknownTarget { monkey: usize, target: usize },
+}
+
+impl Display for InputError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputError::NoMonkeys => write!(f, "no monkeys in the input"),
+            InputError::DuplicateMonkey(id) => write!(f, "monkey {} is defined twice", id),
+            InputError::UnknownTarget { monkey, target } =>
+                write!(f, "monkey {} throws to monkey {}, which does not exist", monkey, target),
+        }
+    }
+}
+
+// Monkeys in turn order, that is sorted by id
+fn parse_input() -> Result<Vec<Monkey>, InputError> {
     let f = File::open("input/input.txt").unwrap();
     let mut lines = io::BufReader::new(f).lines();
-    (0..8).map(|_| Monkey::parse(&mut lines)).collect()
+    let mut monkeys = Vec::new();
+    while let Some(line) = lines.next() {
+        let line = line.unwrap();
+        if !line.trim().is_empty() {
+            monkeys.push(Monkey::parse(&line, &mut lines));
+        }
+    }
+    if monkeys.is_empty() {
+        return Err(InputError::NoMonkeys);
+    }
+
+    monkeys.sort_by_key(|m| m.monkey_id);
+    monkeys.iter_mut().flat_map(|m| m.items.iter_mut()).enumerate().for_each(|(id, item)| item.id = id);
+    if let Some(w) = monkeys.windows(2).find(|w| w[0].monkey_id == w[1].monkey_id) {
+        return Err(InputError::DuplicateMonkey(w[0].monkey_id));
+    }
+    let ids: HashSet<usize> = monkeys.iter().map(|m| m.monkey_id).collect();
+    for m in &monkeys {
+        if let Some(&target) = [m.test_true, m.test_false].iter().find(|t| !ids.contains(t)) {
+            return Err(InputError::UnknownTarget { monkey: m.monkey_id, target });
+        }
+    }
+    Ok(monkeys)
 }
 
-fn compute_monkey_business(monkeys: &mut Vec<Monkey>) -> usize {
+fn compute_monkey_business(monkeys: &[Monkey]) -> usize {
     monkeys.iter()
         .map(|monkey| monkey.inspections)
         .sorted()
@@ -147,29 +420,119 @@ fn compute_monkey_business(monkeys: &mut Vec<Monkey>) -> usize {
         .product::<usize>()
 }
 
-fn make_rounds(mut monkeys: &mut Vec<Monkey>, num_rounds: usize, div3: bool) {
-    let modulo = monkeys.iter().map(|m| m.test).product::<usize>();
-    let mut mailbox: HashMap<usize, Vec<usize>> = HashMap::new();
-    (0..num_rounds).for_each(|_| {
-        monkeys.iter_mut().for_each(|monkey| {
+fn gcd(a: Worry, b: Worry) -> Worry {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+// Worry levels can be kept modulo the lcm of the divisors when every test
+// is a divisibility test and every operation is a polynomial. Dividing by 3
+// does not commute with the modulo, so it rules out any reduction, and so
+// does an lcm too large for a Worry.
+fn reduction_modulus(monkeys: &[Monkey], div3: bool) -> Option<Worry> {
+    if div3 || !monkeys.iter().all(|m| m.op.is_polynomial()) {
+        return None;
+    }
+    monkeys.iter().try_fold(1, |modulo: Worry, m| match m.test {
+        Test::DivisibleBy(n) => (modulo / gcd(modulo, n)).checked_mul(n),
+        _ => None,
+    })
+}
+
+// Returns the modulus worry levels were reduced by, if any
+fn make_rounds(monkeys: &mut [Monkey], num_rounds: usize, div3: bool,
+               mut trace: Option<&mut Trace>) -> Result<Option<Worry>, RoundError> {
+    let modulo = reduction_modulus(monkeys, div3);
+    let mut mailbox: Mailbox = HashMap::new();
+    for round in 1..=num_rounds {
+        for monkey in monkeys.iter_mut() {
             monkey.receive_messages(&mut mailbox);
-            monkey.process_round(&mut mailbox, modulo, div3);
-        });
+            monkey.process_round(&mut mailbox, modulo, div3, round, trace.as_deref_mut())?;
+        }
+        if let Some(trace) = trace.as_deref_mut() {
+            // Items thrown to monkeys that already played are still in the mailbox
+            trace.rounds.extend(monkeys.iter().map(|m| RoundRow {
+                round,
+                monkey: m.monkey_id,
+                inspections: m.inspections,
+                items: m.items.iter().chain(mailbox.get(&m.monkey_id).into_iter().flatten()).copied().collect(),
+            }));
+        }
+    }
+    Ok(modulo)
+}
+
+fn write_csv(path: &str, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) {
+    let res = File::create(path).and_then(|f| {
+        let mut w = BufWriter::new(f);
+        write(&mut w)?;
+        w.flush()
     });
+    match res {
+        Ok(()) => println!("Written {path}"),
+        Err(e) => eprintln!("Error: {e}"),
+    }
+}
+
+fn print_modulus(modulo: Option<Worry>) {
+    match modulo {
+        Some(m) => println!("Worry levels reduced modulo {m}"),
+        None => println!("Worry levels not reduced"),
+    }
 }
 
+// Usage: day_11 [--part 1|2] [--rounds FILE] [--lineage FILE [--item K]]
+// --rounds writes each monkey's inspections and items after every round
+// --lineage writes every inspection, of every item or only of item K
+// Both trace the part selected with --part, part 1 by default.
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let arg_value = |name: &str| {
+        args.iter().position(|a| a == name).map(|idx| args.get(idx + 1).expect("Missing argument value"))
+    };
+    let monkeys = match parse_input() {
+        Ok(monkeys) => monkeys,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return;
+        }
+    };
+
+    let rounds_file = arg_value("--rounds");
+    let lineage_file = arg_value("--lineage");
+    let traced_part = arg_value("--part").map_or(1, |p| p.parse::<u32>().expect("Invalid part"));
+    let mut trace = Trace {
+        item: arg_value("--item").map(|k| k.parse::<usize>().expect("Invalid item")),
+        ..Trace::default()
+    };
+    let tracing = rounds_file.is_some() || lineage_file.is_some();
+    let traced = |part: u32| tracing && part == traced_part;
+
     // Part 1
-    let mut monkeys = parse_input();
-    make_rounds(&mut monkeys, 20, true);
-    let monkey_business = compute_monkey_business(&mut monkeys);
-    println!("Monkey business (part 1): {}", monkey_business);
+    let mut part1 = monkeys.clone();
+    match make_rounds(&mut part1, 20, true, traced(1).then_some(&mut trace)) {
+        Ok(modulo) => {
+            let monkey_business = compute_monkey_business(&part1);
+            println!("Monkey business (part 1): {}", monkey_business);
+            print_modulus(modulo);
+        },
+        Err(e) => eprintln!("Error (part 1): {e}"),
+    }
 
     // Part 2
-    let mut monkeys = parse_input();
-    make_rounds(&mut monkeys, 10000, false);
-    let monkey_business = compute_monkey_business(&mut monkeys);
-    println!("Monkey business (part 1): {}", monkey_business);
-}
-
+    let mut part2 = monkeys;
+    match make_rounds(&mut part2, 10000, false, traced(2).then_some(&mut trace)) {
+        Ok(modulo) => {
+            let monkey_business = compute_monkey_business(&part2);
+            println!("Monkey business (part 2): {}", monkey_business);
+            print_modulus(modulo);
+        },
+        Err(e) => eprintln!("Error (part 2): {e}"),
+    }
 
+    if let Some(path) = rounds_file {
+        write_csv(path, |w| trace.write_rounds_csv(w));
+    }
+    if let Some(path) = lineage_file {
+        write_csv(path, |w| trace.write_lineage_csv(w));
+    }
+}
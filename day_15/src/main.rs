@@ -1,6 +1,6 @@
-use std::cmp::{max, min};
 use std::fs::File;
 use std::io::{self, BufRead};
+use intervals::{Interval, IntervalSet};
 
 #[derive(Copy, Clone, Debug)]
 struct Pos {
@@ -21,71 +21,18 @@ impl Pos {
         self.y.abs_diff(row)
     }
 
-    fn segment_at_row(&self, beacon: &Pos, row: i64) -> Option<Segment> {
+    fn segment_at_row(&self, beacon: &Pos, row: i64) -> Option<Interval> {
         let dist_from_beacon= self.dist(beacon);
         let dist_from_row = self.dist_from_row(row);
         if dist_from_row > dist_from_beacon {
             None
         } else {
             let delta = dist_from_beacon - dist_from_row;
-            Some(Segment::new(self.x - delta as i64, self.x + delta as i64))
+            Some(Interval::closed(self.x - delta as i64, self.x + delta as i64))
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-struct Segment {
-    start: i64,
-    end: i64
-}
-
-impl Segment {
-    fn new(start: i64, end: i64) -> Self {
-        Self { start, end }
-    }
-
-    fn len(&self) -> u64 {
-        (self.end - self.start + 1) as u64
-    }
-
-    fn overlaps(&self, other: &Segment) -> bool {
-        (self.start <= other.start && self.end >= other.start) ||
-            (other.start <= self.start && other.end >= self.start)
-    }
-
-    // Requires self.overlaps(other)
-    fn merge(&self, other: &Segment) -> Segment {
-        let start = min(self.start, other.start);
-        let end = max(self.end, other.end);
-        Segment::new(start, end)
-    }
-
-    fn merge_vec(segments: &mut Vec<Segment>) -> Vec<Segment> {
-        let len = segments.len();
-        segments.sort_by(|s1, s2| s1.start.cmp(&s2.start));
-
-        let mut res = Vec::new();
-        let mut current_seg = segments[0];
-        for idx in 1..len {
-            let next_seg = segments[idx];
-            if current_seg.overlaps(&next_seg) {
-                current_seg = current_seg.merge(&next_seg);
-                if idx == len - 1 {
-                    res.push(current_seg);
-                }
-            } else {
-                res.push(current_seg);
-                current_seg = next_seg;
-                if idx == len - 1 {
-                    res.push(next_seg);
-                }
-            }
-        }
-
-        res
-    }
-}
-
 fn parse_input() -> Vec<(Pos, Pos)> {
     let f = File::open("input/input.txt").unwrap();
     let lines = io::BufReader::new(f).lines();
@@ -99,7 +46,7 @@ fn parse_input() -> Vec<(Pos, Pos)> {
             .replace(",", "")
             .parse::<i64>().unwrap();
 
-        let sy = split.nth(0).unwrap();
+        let sy = split.next().unwrap();
         let sy = sy
             .replace("y=", "")
             .replace(":", "")
@@ -111,23 +58,19 @@ fn parse_input() -> Vec<(Pos, Pos)> {
             .replace(",", "")
             .parse::<i64>().unwrap();
 
-        let by = split.nth(0).unwrap();
+        let by = split.next().unwrap();
         let by = by
             .replace("y=", "")
-            .replace("", "")
             .parse::<i64>().unwrap();
 
         (Pos::new(sx, sy), Pos::new(bx, by))
     }).collect()
 }
 
-fn segments_at_row(input: &Vec<(Pos, Pos)>, row: i64) -> Vec<Segment> {
-    let mut segments: Vec<Segment> = input.iter().filter_map(|&(sensor, beacon)| {
+fn segments_at_row(input: &[(Pos, Pos)], row: i64) -> IntervalSet {
+    input.iter().filter_map(|&(sensor, beacon)| {
         sensor.segment_at_row(&beacon, row)
-    }).collect();
-
-    let merged_segments = Segment::merge_vec(&mut segments);
-    merged_segments
+    }).collect()
 }
 
 fn main() {
@@ -136,9 +79,7 @@ fn main() {
     // Part 1
     // TODO: -1 is hardcoded, should be the existing beacon
     let segments = segments_at_row(&input, 2000000);
-    let count = segments.iter()
-        .map(|seg| seg.len())
-        .sum::<u64>() - 1;
+    let count = segments.coverage() - 1;
     println!("Positions that cannot contain a beacon: {}", count);
 
     // TODO: shame on me for iterating over every possible row!
@@ -147,12 +88,8 @@ fn main() {
             println!("... row {} ...", row);
         }
         let segments = segments_at_row(&input, row);
-        let mut segments: Vec<_> = segments.iter().filter(|s| {
-            s.end > 0 && s.start <= 4000000
-        }).collect();
-        if segments.len() > 1 {
-            segments.sort_by(|s1, s2| s1.start.cmp(&s2.start));
-            let x = segments[0].end + 1;
+        if let Some(gap) = segments.gaps_within(Interval::closed(0, 4000000)).first() {
+            let x = gap.start;
             let y = row;
             let freq = x * 4000000 + y;
             println!("Found! x= {}, y={}, tuning frequency={}", x, y, freq);
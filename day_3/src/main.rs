@@ -1,56 +1,267 @@
-use std::collections::HashSet;
+use std::env;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{self, BufRead};
 
-fn get_priority(c: char) -> u32 {
-    if c.is_lowercase() { c as u32 - 'a' as u32 + 1 }
-    else { c as u32 - 'A' as u32 + 27 }
+// Letters keep their puzzle priorities (1-52); every other printable ASCII
+// character gets the next free priority (53-95), in ASCII order
+fn get_priority(c: char) -> Option<u32> {
+    match c {
+        'a'..='z' => Some(c as u32 - 'a' as u32 + 1),
+        'A'..='Z' => Some(c as u32 - 'A' as u32 + 27),
+        ' '..='~' => Some(53 + (' '..c).filter(|x| !x.is_ascii_alphabetic()).count() as u32),
+        _ => None,
+    }
 }
 
-fn main() {
+fn get_item(priority: u32) -> char {
+    (' '..='~').find(|&c| get_priority(c) == Some(priority)).unwrap()
+}
 
-    // Part 1
-    let f = File::open("input/input.txt").unwrap();
-    let lines = io::BufReader::new(f).lines();
-    let tot: u32 = lines.map(|line| {
-        let mut l1 = line.unwrap();
-        let l2 = l1.split_off(l1.len() / 2);
-        for c in l1.chars() {
-            if let Some(_) = l2.chars().find(|x| *x == c) {
-                return get_priority(c)
+#[derive(Debug)]
+enum RucksackError {
+    InvalidItem { line: usize, item: char },
+    OddLength { line: usize, len: usize },
+    IncompleteGroup { line: usize, found: usize, expected: usize },
+}
+
+impl Display for RucksackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RucksackError::InvalidItem { line, item } =>
+                write!(f, "line {}: item {:?} is not printable ASCII", line, item),
+            RucksackError::OddLength { line, len } =>
+                write!(f, "line {}: {} items cannot be split into two equal compartments", line, len),
+            RucksackError::IncompleteGroup { line, found, expected } =>
+                write!(f, "line {}: trailing group has {} rucksack(s), expected {}", line, found, expected),
+        }
+    }
+}
+
+// Set of items, one bit per priority
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct ItemSet(u128);
+
+impl ItemSet {
+    fn parse(items: &str, line: usize) -> Result<Self, RucksackError> {
+        items.chars().try_fold(ItemSet::default(), |set, item| {
+            match get_priority(item) {
+                Some(p) => Ok(ItemSet(set.0 | 1 << p)),
+                None => Err(RucksackError::InvalidItem { line, item }),
             }
+        })
+    }
+
+    fn all() -> Self {
+        ItemSet(u128::MAX)
+    }
+
+    fn intersect(self, other: ItemSet) -> ItemSet {
+        ItemSet(self.0 & other.0)
+    }
+
+    fn priorities(self) -> impl Iterator<Item = u32> {
+        (0..128).filter(move |p| self.0 & 1 << p != 0)
+    }
+
+    fn priority_sum(self) -> u32 {
+        self.priorities().sum()
+    }
+}
+
+// Splits a rucksack into its two compartments
+fn split_compartments(line: &str, line_num: usize) -> Result<(&str, &str), RucksackError> {
+    if let Some(item) = line.chars().find(|&c| get_priority(c).is_none()) {
+        return Err(RucksackError::InvalidItem { line: line_num, item });
+    }
+    // Valid items are single ASCII bytes, so bytes and items line up
+    if !line.len().is_multiple_of(2) {
+        return Err(RucksackError::OddLength { line: line_num, len: line.len() });
+    }
+    Ok(line.split_at(line.len() / 2))
+}
+
+struct Group {
+    first_line: usize,
+    size: usize,
+    shared: ItemSet,
+}
+
+// Items shared by a group of rucksacks; a group of one shares the items
+// common to its two compartments
+fn shared_items(rucksacks: &[String], first_line: usize) -> Result<ItemSet, RucksackError> {
+    if let [line] = rucksacks {
+        let (c1, c2) = split_compartments(line, first_line)?;
+        return Ok(ItemSet::parse(c1, first_line)?.intersect(ItemSet::parse(c2, first_line)?));
+    }
+    rucksacks.iter().enumerate().try_fold(ItemSet::all(), |shared, (idx, line)| {
+        Ok(shared.intersect(ItemSet::parse(line, first_line + idx)?))
+    })
+}
+
+fn make_groups(lines: &[String], size: usize) -> Result<Vec<Group>, RucksackError> {
+    lines.chunks(size).enumerate().map(|(idx, chunk)| {
+        let first_line = idx * size + 1;
+        if chunk.len() < size {
+            return Err(RucksackError::IncompleteGroup { line: first_line, found: chunk.len(), expected: size });
+        }
+        let shared = shared_items(chunk, first_line)?;
+        Ok(Group { first_line, size, shared })
+    }).collect()
+}
+
+fn print_report(groups: &[Group]) {
+    groups.iter().for_each(|g| {
+        let last_line = g.first_line + g.size - 1;
+        let items: Vec<String> = g.shared.priorities()
+            .map(|p| format!("{:?} ({})", get_item(p), p))
+            .collect();
+        if items.is_empty() {
+            println!("Lines {}-{}: no shared item", g.first_line, last_line);
+        } else {
+            println!("Lines {}-{}: {}", g.first_line, last_line, items.join(", "));
         }
-        0
-    }).sum();
+    });
+}
 
-    println!("{tot}");
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Anomaly {
+    InvalidItem,
+    OddLength,
+    MultipleShared,
+    NoShared,
+}
 
-    // Part 2
-    let f = File::open("input/input.txt").unwrap();
-    let mut lines = io::BufReader::new(f).lines();
-
-    let mut tot: u32 = 0;
-    loop {
-        let line1 = lines.next();
-        let mut s1 = HashSet::new();
-        match line1 {
-            None => {
-                break;
-            },
-            Some(line) => {
-                line.unwrap().chars().for_each(|c| { s1.insert(c); });
+impl Anomaly {
+    fn describe(self) -> &'static str {
+        match self {
+            Anomaly::InvalidItem => "item that is not printable ASCII",
+            Anomaly::OddLength => "odd length, cannot split into equal compartments",
+            Anomaly::MultipleShared => "several items in both compartments",
+            Anomaly::NoShared => "no item in both compartments",
+        }
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            Anomaly::InvalidItem => "invalid_item",
+            Anomaly::OddLength => "odd_length",
+            Anomaly::MultipleShared => "multiple_shared",
+            Anomaly::NoShared => "no_shared",
+        }
+    }
+}
+
+struct AuditEntry {
+    line: usize,
+    shared: ItemSet,
+    anomaly: Option<Anomaly>,
+}
+
+fn audit(lines: &[String]) -> Result<Vec<AuditEntry>, RucksackError> {
+    lines.iter().enumerate().map(|(idx, line)| {
+        let line_num = idx + 1;
+        let (shared, anomaly) = match split_compartments(line, line_num) {
+            Err(RucksackError::InvalidItem { .. }) => (ItemSet::default(), Some(Anomaly::InvalidItem)),
+            Err(RucksackError::OddLength { .. }) => (ItemSet::default(), Some(Anomaly::OddLength)),
+            Err(e) => return Err(e),
+            Ok((c1, c2)) => {
+                let shared = ItemSet::parse(c1, line_num)?.intersect(ItemSet::parse(c2, line_num)?);
+                let anomaly = match shared.priorities().count() {
+                    0 => Some(Anomaly::NoShared),
+                    1 => None,
+                    _ => Some(Anomaly::MultipleShared),
+                };
+                (shared, anomaly)
             }
+        };
+        Ok(AuditEntry { line: line_num, shared, anomaly })
+    }).collect()
+}
+
+fn print_audit_text(entries: &[AuditEntry]) {
+    entries.iter().for_each(|e| {
+        let items: Vec<String> = e.shared.priorities()
+            .map(|p| format!("{:?} ({})", get_item(p), p))
+            .collect();
+        match e.anomaly {
+            None => println!("Line {}: {}", e.line, items.join(", ")),
+            Some(a) if items.is_empty() => println!("Line {}: ANOMALY: {}", e.line, a.describe()),
+            Some(a) => println!("Line {}: {} ANOMALY: {}", e.line, items.join(", "), a.describe()),
         }
-        let mut s2 = HashSet::new();
-        lines.next().unwrap().unwrap().chars().for_each(|c| { s2.insert(c); });
-        let s2: HashSet<_> = s1.intersection(& s2).map(|c| *c).collect();
+    });
+    let anomalies = entries.iter().filter(|e| e.anomaly.is_some()).count();
+    println!("{} line(s), {} anomal{}", entries.len(), anomalies, if anomalies == 1 { "y" } else { "ies" });
+}
 
-        let mut s3 = HashSet::new();
-        lines.next().unwrap().unwrap().chars().for_each(|c| { s3.insert(c); });
-        let s3: HashSet<_> = s2.intersection(& s3).map(|c| *c).collect();
+fn print_audit_json(entries: &[AuditEntry]) {
+    fn json_char(c: char) -> String {
+        match c {
+            '"' | '\\' => format!("\"\\{}\"", c),
+            _ => format!("\"{}\"", c),
+        }
+    }
+
+    println!("[");
+    entries.iter().enumerate().for_each(|(idx, e)| {
+        let items: Vec<String> = e.shared.priorities()
+            .map(|p| format!("{{\"item\": {}, \"priority\": {}}}", json_char(get_item(p)), p))
+            .collect();
+        let anomaly = match e.anomaly {
+            Some(a) => format!("\"{}\"", a.code()),
+            None => String::from("null"),
+        };
+        let sep = if idx + 1 < entries.len() { "," } else { "" };
+        println!("  {{\"line\": {}, \"shared\": [{}], \"anomaly\": {}}}{}", e.line, items.join(", "), anomaly, sep);
+    });
+    println!("]");
+}
+
+fn parse_input() -> Vec<String> {
+    let f = File::open("input/input.txt").unwrap();
+    let lines = io::BufReader::new(f).lines();
+    lines.map(|line| line.unwrap()).collect()
+}
 
-        tot += s3.into_iter().map(get_priority).sum::<u32>();
+fn run(lines: &[String], size: usize, report: bool) -> Result<u32, RucksackError> {
+    let groups = make_groups(lines, size)?;
+    if report {
+        print_report(&groups);
     }
+    Ok(groups.iter().map(|g| g.shared.priority_sum()).sum())
+}
+
+// Usage: day_3 [--report] [--group-size N]
+//        day_3 --audit [--json]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--audit") {
+        match audit(&parse_input()) {
+            Ok(entries) if args.iter().any(|a| a == "--json") => print_audit_json(&entries),
+            Ok(entries) => print_audit_text(&entries),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+        return;
+    }
+
+    let report = args.iter().any(|a| a == "--report");
+    let group_size = args.iter()
+        .position(|a| a == "--group-size")
+        .map(|idx| args.get(idx + 1)
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|&size| size > 0)
+            .expect("Group size must be a positive integer"));
 
-    println!("{tot}");
+    let lines = parse_input();
+    let sizes = match group_size {
+        Some(size) => vec![size],
+        // Part 1 and Part 2
+        None => vec![1, 3],
+    };
+    for size in sizes {
+        match run(&lines, size, report) {
+            Ok(tot) => println!("{tot}"),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
 }
@@ -1,39 +1,115 @@
+use std::env;
 use std::fs::File;
 use std::io::{self, BufRead};
+use intervals::{Interval, IntervalSet, IntervalTree};
 
-fn total_overlap(a: (u32, u32), b: (u32, u32)) -> bool {
-    a.0 <= b.0 && a.1 >= b.1 || b.0 <= a.0 && b.1 >= a.1
+// Position of an assignment in the input: line and elf within the line (both from 1)
+#[derive(Copy, Clone, Debug)]
+struct Assignment {
+    line: usize,
+    elf: usize,
 }
 
-fn partial_overlap(a: (u32, u32), b: (u32, u32)) -> bool {
-    a.0 <= b.0 && a.1 >= b.0 || b.0 <= a.0 && b.1 >= a.0
+struct Group {
+    line: usize,
+    ranges: Vec<Interval>,
 }
 
-fn main() {
-    let f = File::open("input/input.txt").unwrap();
-    let lines = io::BufReader::new(f).lines();
+#[derive(Debug, Default)]
+struct GroupAnalysis {
+    contained: usize,
+    overlapping: Vec<(usize, usize)>,
+    coverage: u128,
+}
+
+impl Group {
+    fn analyze(&self) -> GroupAnalysis {
+        let mut res = GroupAnalysis::default();
+        for (i, a) in self.ranges.iter().enumerate() {
+            for (j, b) in self.ranges.iter().enumerate().skip(i + 1) {
+                if a.nested(b) {
+                    res.contained += 1;
+                }
+                if a.overlaps(b) {
+                    res.overlapping.push((i + 1, j + 1));
+                }
+            }
+        }
+        res.coverage = self.ranges.iter().copied().collect::<IntervalSet>().coverage();
+        res
+    }
+}
 
-    let mut total: u32 = 0;
-    let mut partial: u32 = 0;
+fn parse_range(s: &str) -> Interval {
+    let (start, end) = s.split_once('-').unwrap();
+    Interval::closed(start.parse::<i64>().unwrap(), end.parse::<i64>().unwrap())
+}
 
-    for line in lines {
+fn parse_input() -> Vec<Group> {
+    let f = File::open("input/input.txt").unwrap();
+    let lines = io::BufReader::new(f).lines();
+    lines.enumerate().map(|(idx, line)| {
         let line = line.unwrap();
-        let mut split = line.split(",");
-        let elf1 = split.next().unwrap().split_once("-").unwrap();
-        let elf1 = (elf1.0.parse::<u32>().unwrap(), elf1.1.parse::<u32>().unwrap());
-        let elf2 = split.next().unwrap().split_once("-").unwrap();
-        let elf2 = (elf2.0.parse::<u32>().unwrap(), elf2.1.parse::<u32>().unwrap());
-
-        if total_overlap(elf1, elf2) {
-            total += 1;
+        Group {
+            line: idx + 1,
+            ranges: line.split(',').map(parse_range).collect(),
         }
-        if partial_overlap(elf1, elf2) {
-            partial += 1;
+    }).collect()
+}
+
+fn build_index(groups: &[Group]) -> IntervalTree<Assignment> {
+    groups.iter().flat_map(|g| {
+        g.ranges.iter().enumerate().map(|(idx, &range)| {
+            (range, Assignment { line: g.line, elf: idx + 1 })
+        })
+    }).collect()
+}
+
+fn print_group(group: &Group, analysis: &GroupAnalysis) {
+    let pairs: Vec<String> = analysis.overlapping.iter()
+        .map(|(a, b)| format!("{a}/{b}"))
+        .collect();
+    println!("Line {}: {} range(s), {} contained pair(s), {} overlapping pair(s) [{}], coverage {}",
+             group.line, group.ranges.len(), analysis.contained, analysis.overlapping.len(),
+             pairs.join(" "), analysis.coverage);
+}
+
+fn print_matches(matches: &[&(Interval, Assignment)]) {
+    println!("{} assignment(s)", matches.len());
+    matches.iter().for_each(|(range, a)| println!("  line {}, elf {}: {}", a.line, a.elf, range));
+}
+
+// Usage: day_4 [--groups] [--covers X] [--overlaps A-B]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let arg_value = |name: &str| {
+        args.iter().position(|a| a == name).map(|idx| args.get(idx + 1).expect("Missing argument value"))
+    };
+    let groups = parse_input();
+
+    let mut total = 0;
+    let mut partial = 0;
+    for group in &groups {
+        let analysis = group.analyze();
+        if args.iter().any(|a| a == "--groups") {
+            print_group(group, &analysis);
         }
+        total += analysis.contained;
+        partial += analysis.overlapping.len();
     }
 
     // Part 1
     println!("Total overlap: {total}");
     // Part 2
     println!("Partial overlap: {partial}");
+
+    let index = build_index(&groups);
+    if let Some(x) = arg_value("--covers") {
+        println!("Covering section {x}:");
+        print_matches(&index.covering(x.parse::<i64>().unwrap()));
+    }
+    if let Some(range) = arg_value("--overlaps") {
+        println!("Overlapping {range}:");
+        print_matches(&index.overlapping(parse_range(range)));
+    }
 }
@@ -1,37 +1,71 @@
 use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{self, BufRead};
 
-fn init() -> HashMap<usize, Vec<char>> {
+// (number of crates, from, to)
+type Move = (usize, usize, usize);
+type Stacks = HashMap<usize, Vec<char>>;
+
+// Printed in place of the top crate of an empty stack
+const EMPTY_STACK: char = '-';
+
+#[derive(Debug)]
+enum MoveError {
+    MissingStack { move_idx: usize, stack: usize },
+    NotEnoughCrates { move_idx: usize, stack: usize, requested: usize, available: usize },
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveError::MissingStack { move_idx, stack } =>
+                write!(f, "move {}: stack {} does not exist", move_idx, stack),
+            MoveError::NotEnoughCrates { move_idx, stack, requested, available } =>
+                write!(f, "move {}: cannot take {} crate(s) from stack {}, which holds {}", move_idx, requested, stack, available),
+        }
+    }
+}
+
+fn init() -> Stacks {
     let f = File::open("input/input.txt").unwrap();
     let lines = io::BufReader::new(f).lines();
 
-    let init: Vec<String> = lines.map_while(| line| {
+    let mut drawing: Vec<String> = lines.map_while(| line| {
         let line = line.unwrap();
-        if line.len() <= 1 { None }
+        if line.trim().is_empty() { None }
         else { Some(line) }
     }).collect();
 
-    let mut res = HashMap::new();
-    init.into_iter()
+    // The label row gives both the stack ids and the column of each stack
+    let labels = drawing.pop().expect("Missing stack labels");
+    let columns: Vec<(usize, usize)> = labels.char_indices()
+        .filter(|(_pos, c)| !c.is_whitespace())
+        .map(|(pos, _c)| pos)
+        .filter(|&pos| pos == 0 || labels.as_bytes()[pos - 1] == b' ')
+        .map(|pos| {
+            let label = labels[pos..].split_whitespace().next().unwrap();
+            (pos, label.parse::<usize>().expect("Invalid stack label"))
+        })
+        .collect();
+
+    let mut res: Stacks = columns.iter()
+        .map(|&(_pos, id)| (id, Vec::new()))
+        .collect();
+    drawing.iter()
         .rev()
-        .skip(1)
-        .for_each(| s| {
-            s.char_indices()
-                .skip(1)
-                .step_by(4)
-                .map(|(id, c)| ((id-1)/4+1, c))
-                .filter(|(_id, c)| c.is_alphabetic()).
-                for_each(|(id, c)|
-                    res.entry(id)
-                        .or_insert_with(Vec::new)
-                        .push(c));
+        .for_each(|s| {
+            columns.iter()
+                .filter_map(|&(pos, id)| s.chars().nth(pos).map(|c| (id, c)))
+                .filter(|(_id, c)| c.is_alphabetic())
+                .for_each(|(id, c)| res.get_mut(&id).unwrap().push(c));
         });
 
     res
 }
 
-fn moves() -> Vec<(usize, usize, usize)> {
+fn moves() -> Vec<Move> {
     let f = File::open("input/input.txt").unwrap();
     let lines = io::BufReader::new(f).lines();
 
@@ -43,7 +77,7 @@ fn moves() -> Vec<(usize, usize, usize)> {
             let line = line.replace("move ", "");
             let line = line.replace("from ", "");
             let line = line.replace("to ", "");
-            let mut split = line.split(" ");
+            let mut split = line.split(' ');
             let num = split.next().unwrap().parse::<usize>().unwrap();
             let from = split.next().unwrap().parse::<usize>().unwrap();
             let to = split.next().unwrap().parse::<usize>().unwrap();
@@ -54,38 +88,402 @@ fn moves() -> Vec<(usize, usize, usize)> {
     res
 }
 
-fn do_move(m: (usize, usize, usize), stacks: &mut HashMap<usize, Vec<char>>) {
-    for _i in 0..m.0 {
-        let c = stacks.get_mut(&m.1).unwrap().pop().unwrap();
-        stacks.get_mut(&m.2).unwrap().push(c);
+// Checks that move m (with index move_idx, from 1) can be applied to stacks
+fn validate_move(move_idx: usize, m: Move, stacks: &Stacks) -> Result<(), MoveError> {
+    let (num, from, to) = m;
+    if !stacks.contains_key(&to) {
+        return Err(MoveError::MissingStack { move_idx, stack: to });
+    }
+    match stacks.get(&from) {
+        None => Err(MoveError::MissingStack { move_idx, stack: from }),
+        Some(stack) if stack.len() < num => Err(MoveError::NotEnoughCrates {
+            move_idx,
+            stack: from,
+            requested: num,
+            available: stack.len(),
+        }),
+        Some(_) => Ok(()),
+    }
+}
+
+// Moves the top crates from one stack to another. Implementations can
+// assume the move has already been validated.
+trait Crane {
+    fn name(&self) -> String;
+
+    // Returns the number of lifts used
+    fn do_move(&mut self, m: Move, stacks: &mut Stacks) -> usize;
+
+    fn report(&self) -> Option<String> {
+        None
+    }
+}
+
+// Takes the top num crates off from, keeping their order
+fn take_block(num: usize, from: usize, stacks: &mut Stacks) -> Vec<char> {
+    let stack = stacks.get_mut(&from).unwrap();
+    stack.split_off(stack.len() - num)
+}
+
+// One crate at a time
+struct CrateMover9000;
+
+impl Crane for CrateMover9000 {
+    fn name(&self) -> String {
+        String::from("CrateMover 9000")
+    }
+
+    fn do_move(&mut self, m: Move, stacks: &mut Stacks) -> usize {
+        for _i in 0..m.0 {
+            let c = stacks.get_mut(&m.1).unwrap().pop().unwrap();
+            stacks.get_mut(&m.2).unwrap().push(c);
+        }
+        m.0
+    }
+}
+
+// All the crates in a single lift
+struct CrateMover9001;
+
+impl Crane for CrateMover9001 {
+    fn name(&self) -> String {
+        String::from("CrateMover 9001")
+    }
+
+    fn do_move(&mut self, m: Move, stacks: &mut Stacks) -> usize {
+        let to_move = take_block(m.0, m.1, stacks);
+        stacks.get_mut(&m.2).unwrap().extend(to_move);
+        1
+    }
+}
+
+// At most capacity crates per lift, each lift keeping their order
+struct CapacityCrane {
+    capacity: usize,
+}
+
+impl Crane for CapacityCrane {
+    fn name(&self) -> String {
+        format!("capacity-limited crane (K={})", self.capacity)
+    }
+
+    fn do_move(&mut self, m: Move, stacks: &mut Stacks) -> usize {
+        let mut remaining = m.0;
+        let mut lifts = 0;
+        while remaining > 0 {
+            let num = remaining.min(self.capacity);
+            let to_move = take_block(num, m.1, stacks);
+            stacks.get_mut(&m.2).unwrap().extend(to_move);
+            remaining -= num;
+            lifts += 1;
+        }
+        lifts
+    }
+}
+
+// All the crates in a single lift, flipping their order on odd lifts
+#[derive(Default)]
+struct OddFlipCrane {
+    lifts: usize,
+}
+
+impl Crane for OddFlipCrane {
+    fn name(&self) -> String {
+        String::from("odd-flip crane")
+    }
+
+    fn do_move(&mut self, m: Move, stacks: &mut Stacks) -> usize {
+        self.lifts += 1;
+        let mut to_move = take_block(m.0, m.1, stacks);
+        if self.lifts % 2 == 1 {
+            to_move.reverse();
+        }
+        stacks.get_mut(&m.2).unwrap().extend(to_move);
+        1
+    }
+}
+
+// Wraps another crane, counting its lifts and the distance it travels
+// (number of stacks crossed by each lift)
+struct MeteredCrane {
+    inner: Box<dyn Crane>,
+    lifts: usize,
+    distance: usize,
+}
+
+impl MeteredCrane {
+    fn new(inner: Box<dyn Crane>) -> Self {
+        Self { inner, lifts: 0, distance: 0 }
+    }
+}
+
+impl Crane for MeteredCrane {
+    fn name(&self) -> String {
+        format!("metered {}", self.inner.name())
+    }
+
+    fn do_move(&mut self, m: Move, stacks: &mut Stacks) -> usize {
+        let lifts = self.inner.do_move(m, stacks);
+        self.lifts += lifts;
+        self.distance += lifts * m.1.abs_diff(m.2);
+        lifts
+    }
+
+    fn report(&self) -> Option<String> {
+        Some(format!("Lifts: {} - Distance: {}", self.lifts, self.distance))
+    }
+}
+
+fn make_crane(model: &str) -> Box<dyn Crane> {
+    match model.split_once('=') {
+        None if model == "9000" => Box::new(CrateMover9000),
+        None if model == "9001" => Box::new(CrateMover9001),
+        None if model == "odd-flip" => Box::new(OddFlipCrane::default()),
+        Some(("capacity", k)) => {
+            let capacity = k.parse::<usize>().ok().filter(|&k| k > 0).expect("Invalid crane capacity");
+            Box::new(CapacityCrane { capacity })
+        },
+        _ => panic!("Unknown crane model {model}"),
+    }
+}
+
+// Applies every move, calling on_move with the move index (from 1) and the
+// stacks after the move
+fn run(moves: &[Move],
+       stacks: &mut Stacks,
+       crane: &mut dyn Crane,
+       mut on_move: impl FnMut(usize, Move, &Stacks)) -> Result<(), MoveError> {
+    moves.iter().enumerate().try_for_each(|(idx, &m)| {
+        validate_move(idx + 1, m, stacks)?;
+        crane.do_move(m, stacks);
+        on_move(idx + 1, m, stacks);
+        Ok(())
+    })
+}
+
+// Effect of an applied move: the crates it took from stack from (bottom to
+// top) and the ones it placed on stack to. Enough to both redo and undo the
+// move without asking the crane again.
+struct Step {
+    from: usize,
+    to: usize,
+    taken: Vec<char>,
+    placed: Vec<char>,
+}
+
+impl Step {
+    fn redo(&self, stacks: &mut Stacks) {
+        take_block(self.taken.len(), self.from, stacks);
+        stacks.get_mut(&self.to).unwrap().extend(&self.placed);
+    }
+
+    // Applies the inverse move
+    fn undo(&self, stacks: &mut Stacks) {
+        take_block(self.placed.len(), self.to, stacks);
+        stacks.get_mut(&self.from).unwrap().extend(&self.taken);
     }
 }
 
-fn do_move_9001(m: (usize, usize, usize), stacks: &mut HashMap<usize, Vec<char>>) {
-    let mut to_move : Vec<char> = (0..m.0).into_iter()
-        .map(|_| stacks.get_mut(&m.1).unwrap().pop().unwrap())
+// Step-through replay of the moves with a given crane
+struct Replay {
+    moves: Vec<Move>,
+    crane: Box<dyn Crane>,
+    stacks: Stacks,
+    // Every move applied so far, including the undone ones
+    steps: Vec<Step>,
+    // Number of moves currently applied
+    pos: usize,
+}
+
+impl Replay {
+    fn new(moves: Vec<Move>, crane: Box<dyn Crane>, stacks: Stacks) -> Self {
+        Self { moves, crane, stacks, steps: Vec::new(), pos: 0 }
+    }
+
+    // Returns false at the end of the moves
+    fn forward(&mut self) -> Result<bool, MoveError> {
+        if self.pos == self.moves.len() {
+            return Ok(false);
+        }
+        if self.pos < self.steps.len() {
+            self.steps[self.pos].redo(&mut self.stacks);
+        } else {
+            let m = self.moves[self.pos];
+            validate_move(self.pos + 1, m, &self.stacks)?;
+            let (num, from, to) = m;
+            let taken = self.stacks[&from][self.stacks[&from].len() - num..].to_vec();
+            self.crane.do_move(m, &mut self.stacks);
+            let placed = self.stacks[&to][self.stacks[&to].len() - num..].to_vec();
+            self.steps.push(Step { from, to, taken, placed });
+        }
+        self.pos += 1;
+        Ok(true)
+    }
+
+    // Returns false at the beginning of the moves
+    fn back(&mut self) -> bool {
+        if self.pos == 0 {
+            return false;
+        }
+        self.pos -= 1;
+        self.steps[self.pos].undo(&mut self.stacks);
+        true
+    }
+
+    // Moves to the state after the first n moves
+    fn jump(&mut self, n: usize) -> Result<(), MoveError> {
+        let n = n.min(self.moves.len());
+        while self.pos > n {
+            self.back();
+        }
+        while self.pos < n {
+            self.forward()?;
+        }
+        Ok(())
+    }
+}
+
+// Renders the stacks in the format of the puzzle input
+fn render(stacks: &Stacks) -> Vec<String> {
+    let mut ids: Vec<_> = stacks.keys().collect();
+    ids.sort();
+    let height = stacks.values().map(|s| s.len()).max().unwrap_or(0);
+
+    let mut res: Vec<String> = (0..height).rev().map(|level| {
+        ids.iter()
+            .map(|i| match stacks[i].get(level) {
+                Some(c) => format!("[{c}]"),
+                None => String::from("   "),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }).collect();
+    res.push(ids.iter().map(|i| format!("{:^3}", i)).collect::<Vec<_>>().join(" "));
+    res
+}
+
+// Prints several renderings next to each other, aligned at the bottom
+fn print_side_by_side(titles: &[String], renderings: &[Vec<String>]) {
+    let widths: Vec<usize> = titles.iter().zip(renderings)
+        .map(|(t, r)| r.iter().chain([t]).map(|l| l.len()).max().unwrap())
         .collect();
-    to_move.reverse();
-    stacks.get_mut(&m.2).unwrap().extend(to_move);
+    let height = renderings.iter().map(|r| r.len()).max().unwrap_or(0);
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<String> = cells.iter().zip(&widths).map(|(c, w)| format!("{:w$}", c, w = w)).collect();
+        println!("{}", cells.join("    ").trim_end());
+    };
+    line(titles.iter().map(|t| t.as_str()).collect());
+    for row in 0..height {
+        line(renderings.iter().map(|r| {
+            let offset = height - r.len();
+            if row < offset { "" } else { r[row - offset].as_str() }
+        }).collect());
+    }
 }
 
-fn print_top(stacks: & HashMap<usize, Vec<char>>) {
-    for i in 1..10 {
-        print!("{}", stacks.get(&i).unwrap().last().unwrap())
+fn print_snapshot(idx: usize, m: Move, stacks: &Stacks) {
+    println!("Move {}: {} from {} to {}", idx, m.0, m.1, m.2);
+    render(stacks).iter().for_each(|line| println!("{line}"));
+}
+
+// Reads navigation commands from stdin, applying them to all replays at once:
+// n (next), b (back, undoes the last move), g N (go to move N), q (quit)
+fn replay(replays: &mut [Replay]) {
+    let print_state = |replays: &[Replay]| {
+        let pos = replays[0].pos;
+        match pos {
+            0 => println!("Initial state"),
+            _ => {
+                let m = replays[0].moves[pos - 1];
+                println!("After move {}/{}: {} from {} to {}", pos, replays[0].moves.len(), m.0, m.1, m.2);
+            }
+        }
+        let titles: Vec<String> = replays.iter().map(|r| r.crane.name()).collect();
+        let renderings: Vec<Vec<String>> = replays.iter().map(|r| render(&r.stacks)).collect();
+        print_side_by_side(&titles, &renderings);
+    };
+
+    print_state(replays);
+    for line in io::stdin().lock().lines() {
+        let line = line.unwrap();
+        let mut split = line.split_whitespace();
+        let result = match split.next().unwrap_or("n") {
+            "n" => replays.iter_mut().try_for_each(|r| r.forward().map(|_| ())),
+            "b" => {
+                replays.iter_mut().for_each(|r| { r.back(); });
+                Ok(())
+            },
+            "g" => match split.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) => replays.iter_mut().try_for_each(|r| r.jump(n)),
+                None => {
+                    eprintln!("Usage: g N");
+                    continue;
+                }
+            },
+            "q" => break,
+            cmd => {
+                eprintln!("Unknown command {cmd}");
+                continue;
+            }
+        };
+        match result {
+            Ok(()) => print_state(replays),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+}
+
+fn print_top(stacks: & Stacks) {
+    let mut ids: Vec<_> = stacks.keys().collect();
+    ids.sort();
+    for i in ids {
+        print!("{}", stacks[i].last().unwrap_or(&EMPTY_STACK))
     }
     println!();
 }
 
+// Usage: day_5 [--crane 9000|9001|odd-flip|capacity=K] [--metered] [--snapshots] [--replay]
 fn main() {
-    let moves = moves();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let metered = args.iter().any(|a| a == "--metered");
+    let snapshots = args.iter().any(|a| a == "--snapshots");
+    let models = match args.iter().position(|a| a == "--crane") {
+        Some(idx) => vec![args.get(idx + 1).expect("Missing crane model").as_str()],
+        // Part 1 and Part 2
+        None => vec!["9000", "9001"],
+    };
 
-    // Part 1
-    let mut stacks = init();
-    moves.iter().for_each(|&m| do_move(m, &mut stacks));
-    print_top(&stacks);
+    let moves = moves();
+    if args.iter().any(|a| a == "--replay") {
+        let mut replays: Vec<Replay> = models.iter()
+            .map(|model| Replay::new(moves.clone(), make_crane(model), init()))
+            .collect();
+        replay(&mut replays);
+        return;
+    }
 
-    // Part 2
-    let mut stacks = init();
-    moves.iter().for_each(|&m| do_move_9001(m, &mut stacks));
-    print_top(&stacks);
+    for model in models {
+        let mut crane = make_crane(model);
+        if metered {
+            crane = Box::new(MeteredCrane::new(crane));
+        }
+        let mut stacks = init();
+        let result = run(&moves, &mut stacks, crane.as_mut(), |idx, m, stacks| {
+            if snapshots {
+                print_snapshot(idx, m, stacks);
+            }
+        });
+        match result {
+            Ok(()) => {
+                if snapshots || metered {
+                    println!("{}:", crane.name());
+                }
+                print_top(&stacks);
+                if let Some(report) = crane.report() {
+                    println!("{report}");
+                }
+            },
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
 }
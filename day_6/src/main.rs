@@ -1,36 +1,295 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, Read};
 
-fn first_no_rep(s: &str, size: usize) -> usize {
-    let s = s.as_bytes();
-    let mut res = 0;
-    for i in size-1..s.len() {
-        let mut v: Vec<_> = (i+1-size..i+1).into_iter().map(|id| s[id]).collect();
-        v.sort();
-        v.dedup();
-        if v.len() == size {
-            res = i+1;
-            break;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Symbols a datastream is made of
+#[derive(Clone, Debug)]
+enum Alphabet {
+    // Every byte value
+    Bytes,
+    // Every Unicode scalar value, UTF-8 encoded
+    Utf8,
+    // The given chars only, UTF-8 encoded
+    Chars(Vec<char>),
+}
+
+impl Alphabet {
+    fn size(&self) -> usize {
+        match self {
+            Alphabet::Bytes => 256,
+            Alphabet::Utf8 => char::MAX as usize + 1,
+            Alphabet::Chars(chars) => chars.len(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ScanError {
+    Io(io::Error),
+    InvalidUtf8 { offset: usize },
+    OutsideAlphabet { offset: usize, c: char },
+}
+
+impl Display for ScanError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanError::Io(e) => write!(f, "{}", e),
+            ScanError::InvalidUtf8 { offset } => write!(f, "invalid UTF-8 at byte {}", offset),
+            ScanError::OutsideAlphabet { offset, c } => write!(f, "{:?} at byte {} is not in the alphabet", c, offset),
         }
     }
-    
-    res
 }
 
-fn start_of_packet(s: &str) -> usize {
-    first_no_rep(s, 4)
+impl From<io::Error> for ScanError {
+    fn from(e: io::Error) -> Self {
+        ScanError::Io(e)
+    }
+}
+
+// Reads a stream chunk by chunk, turning it into symbol ids of an alphabet
+struct Symbols<R: Read> {
+    reader: R,
+    alphabet: Alphabet,
+    char_ids: HashMap<char, u32>,
+    // The datastream ends at the first \n or \r\n
+    stop_at_newline: bool,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    // Stream offset of buf[start]
+    offset: usize,
+    done: bool,
+}
+
+impl<R: Read> Symbols<R> {
+    fn new(reader: R, alphabet: Alphabet, stop_at_newline: bool) -> Self {
+        let char_ids = match &alphabet {
+            Alphabet::Chars(chars) => chars.iter().enumerate().map(|(id, &c)| (c, id as u32)).collect(),
+            _ => HashMap::new(),
+        };
+        Self {
+            reader,
+            alphabet,
+            char_ids,
+            stop_at_newline,
+            buf: vec![0; CHUNK_SIZE],
+            start: 0,
+            end: 0,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    // Makes sure at least need bytes are buffered; false if the stream ends first
+    fn fill(&mut self, need: usize) -> io::Result<bool> {
+        if self.end - self.start >= need {
+            return Ok(true);
+        }
+        self.buf.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+        while self.end < need {
+            match self.reader.read(&mut self.buf[self.end..]) {
+                Ok(0) => return Ok(false),
+                Ok(len) => self.end += len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    fn next_symbol(&mut self) -> Result<Option<u32>, ScanError> {
+        if self.done || !self.fill(1)? {
+            return Ok(None);
+        }
+        let first = self.buf[self.start];
+        if self.stop_at_newline && first == b'\n' {
+            return Ok(None);
+        }
+        // A lone \r is an ordinary symbol
+        if self.stop_at_newline && first == b'\r' && self.fill(2)? && self.buf[self.start + 1] == b'\n' {
+            return Ok(None);
+        }
+        if let Alphabet::Bytes = self.alphabet {
+            self.start += 1;
+            self.offset += 1;
+            return Ok(Some(first as u32));
+        }
+
+        let len = match first {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => return Err(ScanError::InvalidUtf8 { offset: self.offset }),
+        };
+        if !self.fill(len)? {
+            return Err(ScanError::InvalidUtf8 { offset: self.offset });
+        }
+        let c = std::str::from_utf8(&self.buf[self.start..self.start + len])
+            .map_err(|_| ScanError::InvalidUtf8 { offset: self.offset })?
+            .chars().next().unwrap();
+        let id = match self.alphabet {
+            Alphabet::Chars(_) => *self.char_ids.get(&c)
+                .ok_or(ScanError::OutsideAlphabet { offset: self.offset, c })?,
+            _ => c as u32,
+        };
+        self.start += len;
+        self.offset += len;
+        Ok(Some(id))
+    }
+}
+
+impl<R: Read> Iterator for Symbols<R> {
+    type Item = Result<u32, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.next_symbol().transpose();
+        if !matches!(res, Some(Ok(_))) {
+            self.done = true;
+        }
+        res
+    }
 }
 
-fn start_of_message(s: &str) -> usize {
-    first_no_rep(s, 14)
+// Sliding window over the last size symbols of a stream, tracking how many
+// distinct symbols it holds
+struct MarkerDetector {
+    size: usize,
+    window: Vec<u32>,
+    counts: Vec<u32>,
+    distinct: usize,
+    // Symbols seen so far
+    pos: usize,
 }
 
+impl MarkerDetector {
+    fn new(size: usize, alphabet: &Alphabet) -> Self {
+        assert!(size > 0, "Marker size must be positive");
+        Self {
+            size,
+            window: vec![0; size],
+            counts: vec![0; alphabet.size()],
+            distinct: 0,
+            pos: 0,
+        }
+    }
+
+    // Returns true if the last size symbols, including s, are all different
+    fn push(&mut self, s: u32) -> bool {
+        let slot = self.pos % self.size;
+        if self.pos >= self.size {
+            let old = self.window[slot] as usize;
+            self.counts[old] -= 1;
+            if self.counts[old] == 0 {
+                self.distinct -= 1;
+            }
+        }
+        self.window[slot] = s;
+        self.counts[s as usize] += 1;
+        if self.counts[s as usize] == 1 {
+            self.distinct += 1;
+        }
+        self.pos += 1;
+        self.distinct == self.size
+    }
+}
+
+// Number of bytes read up to the end of the first marker of the given size,
+// or None if the stream has no marker. The datastream ends at the first
+// newline, if any.
+fn first_no_rep(reader: impl Read, size: usize) -> Result<Option<usize>, ScanError> {
+    let mut detector = MarkerDetector::new(size, &Alphabet::Bytes);
+    for s in Symbols::new(reader, Alphabet::Bytes, true) {
+        if detector.push(s?) {
+            return Ok(Some(detector.pos));
+        }
+    }
+    Ok(None)
+}
+
+// Every marker for each of the given sizes, in a single pass. Markers are
+// reported as the number of symbols read up to their end.
+fn scan_markers(reader: impl Read,
+                sizes: &[usize],
+                alphabet: Alphabet,
+                stop_at_newline: bool) -> Result<Vec<Vec<usize>>, ScanError> {
+    let mut detectors: Vec<MarkerDetector> = sizes.iter()
+        .map(|&size| MarkerDetector::new(size, &alphabet))
+        .collect();
+    let mut res = vec![Vec::new(); sizes.len()];
+    for s in Symbols::new(reader, alphabet, stop_at_newline) {
+        let s = s?;
+        detectors.iter_mut().zip(res.iter_mut()).for_each(|(detector, markers)| {
+            if detector.push(s) {
+                markers.push(detector.pos);
+            }
+        });
+    }
+    Ok(res)
+}
+
+fn start_of_packet(reader: impl Read) -> Result<Option<usize>, ScanError> {
+    first_no_rep(reader, 4)
+}
+
+fn start_of_message(reader: impl Read) -> Result<Option<usize>, ScanError> {
+    first_no_rep(reader, 14)
+}
+
+fn print_marker(label: &str, marker: Result<Option<usize>, ScanError>) {
+    match marker {
+        Ok(Some(pos)) => println!("{label}: {pos}"),
+        Ok(None) => println!("{label}: no marker"),
+        Err(e) => eprintln!("Error: {e}"),
+    }
+}
+
+fn parse_alphabet(s: &str) -> Alphabet {
+    match s {
+        "bytes" => Alphabet::Bytes,
+        "utf8" => Alphabet::Utf8,
+        _ => match s.strip_prefix("chars=") {
+            Some(chars) => Alphabet::Chars(chars.chars().collect()),
+            None => panic!("Unknown alphabet {s}"),
+        },
+    }
+}
+
+// Usage: day_6 [--all K[,K...] [--alphabet bytes|utf8|chars=...] [--raw]]
+// --raw scans the whole stream instead of stopping at the first newline
 fn main() {
-    let f = File::open("input/input.txt").unwrap();
-    let mut lines = io::BufReader::new(f).lines();
-    let line = lines.next().unwrap().unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let arg_value = |name: &str| {
+        args.iter().position(|a| a == name).map(|idx| args.get(idx + 1).expect("Missing argument value"))
+    };
+
+    if let Some(sizes) = arg_value("--all") {
+        let sizes: Vec<usize> = sizes.split(',')
+            .map(|k| k.parse::<usize>().ok().filter(|&k| k > 0).expect("Invalid marker size"))
+            .collect();
+        let alphabet = arg_value("--alphabet").map_or(Alphabet::Bytes, |a| parse_alphabet(a));
+        let stop_at_newline = !args.iter().any(|a| a == "--raw");
+        let f = File::open("input/input.txt").unwrap();
+        match scan_markers(f, &sizes, alphabet, stop_at_newline) {
+            Ok(markers) => sizes.iter().zip(markers).for_each(|(k, markers)| {
+                let offsets: Vec<String> = markers.iter().map(|m| m.to_string()).collect();
+                println!("k={}: {} marker(s): {}", k, markers.len(), offsets.join(","));
+            }),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+        return;
+    }
+
     // Part 1
-    println!("SoP: {}", start_of_packet(&line));
+    let f = File::open("input/input.txt").unwrap();
+    print_marker("SoP", start_of_packet(f));
     // Part 2
-    println!("SoM: {}", start_of_message(&line));
+    let f = File::open("input/input.txt").unwrap();
+    print_marker("SoM", start_of_message(f));
 }
@@ -1,19 +1,20 @@
 use std::collections::HashMap;
+use std::env;
 use std::fs::File;
 use std::io::{self, BufRead};
-use crate::Cmd::{CD, DIR, FILE, LS};
+use crate::Cmd::{Cd, Dir, File as FileEntry, Ls};
 
 #[derive(Debug)]
 enum Cmd {
-    CD {
+    Cd {
         path: String
     },
-    LS,
-    FILE {
+    Ls,
+    File {
         size: usize,
         name: String,
     },
-    DIR {
+    Dir {
         name: String,
     }
 }
@@ -21,96 +22,603 @@ enum Cmd {
 #[derive(Debug)]
 struct Node {
     name: String,
+    // For directories, total size of the files they contain
     size: usize,
-    children: HashMap<String, Node>,
+    // Ids of the children, by name
+    children: HashMap<String, usize>,
+    parent: Option<usize>,
     dir: bool,
 }
 
 impl Node {
-    fn new_dir(name: &str) -> Self {
+    fn new_dir(name: &str, parent: Option<usize>) -> Self {
         Self {
             name: String::from(name),
             size: 0,
             children: HashMap::new(),
+            parent,
             dir: true
         }
     }
 
-    fn new_file(name: &str, size: usize) -> Self {
+    fn new_file(name: &str, size: usize, parent: usize) -> Self {
         Self {
             name: String::from(name),
             size,
             children: HashMap::new(),
+            parent: Some(parent),
             dir: false
         }
     }
+}
+
+// Filesystem rebuilt from a shell transcript. Nodes are stored by id, with
+// the root at id 0 and every node after its parent.
+struct Vfs {
+    nodes: Vec<Node>,
+    // Ids of the directories from the root to the working directory
+    cwd: Vec<usize>,
+}
+
+impl Vfs {
+    const ROOT: usize = 0;
+
+    fn new() -> Self {
+        Self {
+            nodes: vec![Node::new_dir("/", None)],
+            cwd: vec![Self::ROOT],
+        }
+    }
+
+    fn root(&self) -> &Node {
+        &self.nodes[Self::ROOT]
+    }
+
+    fn cwd(&self) -> usize {
+        *self.cwd.last().unwrap()
+    }
 
-    fn parse(&mut self, it: &mut impl Iterator<Item = Cmd>) {
-        if let Some(cmd) = it.next() {
-            match cmd {
-                LS => { },
-                CD { path } => {
-                    match path.as_str() {
-                        ".." => { return; },
-                        "/" => { },
-                        _ => {
-                            let dir = self.children.get_mut(&path).expect("Dir not listed");
-                            dir.parse(it);
-                            self.size += dir.size;
-                        }
+    // Sub directory name of dir, created if it was never listed
+    fn child_dir(&mut self, dir: usize, name: &str) -> usize {
+        match self.nodes[dir].children.get(name) {
+            Some(&id) => {
+                assert!(self.nodes[id].dir, "{} is a file", name);
+                id
+            },
+            None => {
+                let id = self.nodes.len();
+                self.nodes.push(Node::new_dir(name, Some(dir)));
+                self.nodes[dir].children.insert(String::from(name), id);
+                id
+            }
+        }
+    }
+
+    // Adds (or replaces) a file in the working directory
+    fn add_file(&mut self, name: &str, size: usize) {
+        let dir = self.cwd();
+        match self.nodes[dir].children.get(name) {
+            Some(&id) => {
+                assert!(!self.nodes[id].dir, "{} is a directory", name);
+                self.nodes[id].size = size;
+            },
+            None => {
+                let id = self.nodes.len();
+                self.nodes.push(Node::new_file(name, size, dir));
+                self.nodes[dir].children.insert(String::from(name), id);
+            }
+        }
+    }
+
+    fn cd(&mut self, path: &str) {
+        if path.starts_with('/') {
+            self.cwd.truncate(1);
+        }
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            match name {
+                "." => { },
+                ".." => {
+                    if self.cwd.len() > 1 {
+                        self.cwd.pop();
                     }
                 },
-                DIR { name} => {
-                    self.children.insert(name.clone(), Node::new_dir(&name.clone()));
-                },
-                FILE { size, name } => {
-                    self.children.insert(name.clone(), Node::new_file(&name.clone(), size));
-                    self.size += size;
+                _ => {
+                    let id = self.child_dir(self.cwd(), name);
+                    self.cwd.push(id);
                 }
             }
-            self.parse(it);
         }
     }
 
-    fn print_visit(&self) {
-        println!("{} --> {:?}", self.name, self.children);
-        self.children.iter().for_each(|(_, child)| child.print_visit());
+    fn apply(&mut self, cmd: Cmd) {
+        match cmd {
+            Cd { path } => self.cd(&path),
+            // Listing output is merged with what is already known
+            Ls => { },
+            Dir { name } => { self.child_dir(self.cwd(), &name); },
+            FileEntry { size, name } => self.add_file(&name, size),
+        }
     }
 
-    fn part1_visit(&self, acc: &mut usize) {
-        if self.dir && self.size < 100000 {
-            *acc += self.size;
+    // Children come after their parent, so a reverse scan sees every
+    // directory only once its content is complete
+    fn compute_sizes(&mut self) {
+        self.nodes.iter_mut().filter(|node| node.dir).for_each(|node| node.size = 0);
+        for id in (1..self.nodes.len()).rev() {
+            let size = self.nodes[id].size;
+            let parent = self.nodes[id].parent.unwrap();
+            self.nodes[parent].size += size;
+        }
+    }
+
+    fn parse(it: impl Iterator<Item = Cmd>) -> Self {
+        let mut vfs = Vfs::new();
+        it.for_each(|cmd| vfs.apply(cmd));
+        vfs.compute_sizes();
+        vfs
+    }
+
+    // Absolute path of node id
+    fn path(&self, id: usize) -> String {
+        let mut names = Vec::new();
+        let mut current = id;
+        while let Some(parent) = self.nodes[current].parent {
+            names.push(self.nodes[current].name.as_str());
+            current = parent;
+        }
+        names.reverse();
+        format!("/{}", names.join("/"))
+    }
+
+    fn sorted_children(&self, id: usize) -> Vec<usize> {
+        let mut children: Vec<usize> = self.nodes[id].children.values().copied().collect();
+        children.sort_by(|a, b| self.nodes[*a].name.cmp(&self.nodes[*b].name));
+        children
+    }
+
+    // (id, depth) of every node, parents first and siblings sorted by name
+    fn preorder(&self) -> Vec<(usize, usize)> {
+        self.traverse(|children| children.reverse())
+    }
+
+    // (id, depth) of every node, children first and siblings sorted by name
+    fn postorder(&self) -> Vec<(usize, usize)> {
+        // A preorder visiting siblings backwards, reversed
+        let mut res = self.traverse(|_| { });
+        res.reverse();
+        res
+    }
+
+    fn traverse(&self, order_stack: fn(&mut Vec<usize>)) -> Vec<(usize, usize)> {
+        let mut res = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![(Self::ROOT, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            res.push((id, depth));
+            let mut children = self.sorted_children(id);
+            order_stack(&mut children);
+            stack.extend(children.into_iter().map(|child| (child, depth + 1)));
         }
-        self.children.iter().for_each(|(_, child)| child.part1_visit(acc));
+        res
+    }
+
+    fn part1_visit(&self, acc: &mut usize) {
+        *acc += self.nodes.iter()
+            .filter(|node| node.dir && node.size < 100000)
+            .map(|node| node.size)
+            .sum::<usize>();
     }
 
     fn part2_visit(&self, to_delete: usize, acc: &mut usize) {
-        if self.dir && self.size >= to_delete && self.size < *acc {
-            *acc = self.size;
+        for node in &self.nodes {
+            if node.dir && node.size >= to_delete && node.size < *acc {
+                *acc = node.size;
+            }
         }
-        self.children.iter().for_each(|(_, child)| child.part2_visit(to_delete, acc));
     }
 }
 
-fn parse_line(line: &str) -> Cmd {
-    match &line[0..4] {
-        "$ cd" => CD {
-            path: String::from(&line[5..line.len()])
-        },
-        "$ ls" => LS,
-        "dir " => DIR {
-            name: String::from(&line[4..line.len()])
+// Fixed-size set of small integers
+#[derive(Clone)]
+struct BitSet {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitSet {
+    fn new(len: usize) -> Self {
+        Self { words: vec![0; len.div_ceil(64)], len }
+    }
+
+    fn get(&self, i: usize) -> bool {
+        i < self.len && self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set(&mut self, i: usize) {
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    // self |= other << shift, dropping what falls beyond len
+    fn or_shifted(&mut self, other: &BitSet, shift: usize) {
+        let (word_shift, bit_shift) = (shift / 64, shift % 64);
+        for i in (word_shift..self.words.len()).rev() {
+            let src = i - word_shift;
+            let mut w = other.words[src] << bit_shift;
+            if bit_shift > 0 && src > 0 {
+                w |= other.words[src - 1] >> (64 - bit_shift);
+            }
+            self.words[i] |= w;
+        }
+        if !self.len.is_multiple_of(64) {
+            let last = self.words.len() - 1;
+            self.words[last] &= (1 << (self.len % 64)) - 1;
+        }
+    }
+
+    // Smallest element >= from
+    fn first_from(&self, from: usize) -> Option<usize> {
+        if from >= self.len {
+            return None;
+        }
+        let first = self.words[from / 64] & (u64::MAX << (from % 64));
+        std::iter::once(first).chain(self.words[from / 64 + 1..].iter().copied())
+            .position(|w| w != 0)
+            .map(|idx| {
+                let word = from / 64 + idx;
+                let w = if idx == 0 { first } else { self.words[word] };
+                word * 64 + w.trailing_zeros() as usize
+            })
+    }
+}
+
+// How the cheapest deletion reaching the target is obtained from a
+// position of the preorder
+#[derive(Copy, Clone)]
+enum Choice {
+    Skip,
+    // Delete the directory, then continue after its subtree with the cheapest
+    // deletion reaching the target
+    Take,
+    // Delete the directory, then free exactly the given amount after its subtree
+    TakeExact(usize),
+}
+
+// Upper bound on the bits of the tables built by plan_deletion, 32 MB
+const MAX_PLAN_BITS: usize = 1 << 28;
+
+struct DeletionPlan {
+    to_delete: usize,
+    // Directories to delete and the space they free
+    dirs: Vec<usize>,
+    freed: usize,
+    // Unit sizes were rounded down to while planning; the plan is the
+    // smallest possible only when this is 1
+    granularity: usize,
+}
+
+impl Vfs {
+    fn space_to_free(&self, total: usize, required: usize) -> usize {
+        (self.root().size + required).saturating_sub(total)
+    }
+
+    // Every directory that frees enough space on its own, smallest first
+    fn single_deletions(&self, to_delete: usize) -> Vec<usize> {
+        let mut res: Vec<usize> = (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].dir && self.nodes[id].size >= to_delete)
+            .collect();
+        res.sort_by_key(|&id| self.nodes[id].size);
+        res
+    }
+
+    // Set of non-nested directories with the smallest total size that frees
+    // at least to_delete, or None if even deleting everything is not enough.
+    fn plan_deletion(&self, to_delete: usize) -> Option<DeletionPlan> {
+        self.plan_deletion_within(to_delete, MAX_PLAN_BITS)
+    }
+
+    // Knapsack over the preorder of the directories: from position i one
+    // either skips dir i or deletes it and jumps past its subtree. Sums below
+    // the target are tracked exactly in a bitset per position, larger ones
+    // only by their minimum.
+    //
+    // The bitsets take (directories + 1) x to_delete bits, and building them
+    // as many bit operations / 64. When that is more than max_bits, sizes are
+    // counted in units of a granularity g, rounded down, and the target in
+    // units rounded up: the plan still frees enough, but it may free up to
+    // about g per deleted directory more than the best one.
+    fn plan_deletion_within(&self, to_delete: usize, max_bits: usize) -> Option<DeletionPlan> {
+        if to_delete == 0 {
+            return Some(DeletionPlan { to_delete, dirs: Vec::new(), freed: 0, granularity: 1 });
+        }
+        if self.root().size < to_delete {
+            return None;
+        }
+        let dirs: Vec<usize> = self.preorder().into_iter()
+            .map(|(id, _)| id)
+            .filter(|&id| self.nodes[id].dir)
+            .collect();
+        let n = dirs.len();
+
+        let granularity = ((n + 1) as u128 * to_delete as u128).div_ceil(max_bits.max(1) as u128).max(1) as usize;
+        let target = to_delete.div_ceil(granularity);
+        let sizes: Vec<usize> = dirs.iter().map(|&id| self.nodes[id].size / granularity).collect();
+
+        // Position after the subtree of each dir
+        let mut subtree_end = vec![n; n];
+        let mut open: Vec<usize> = Vec::new();
+        for (pos, &id) in dirs.iter().enumerate() {
+            while let Some(&top) = open.last() {
+                if self.nodes[id].parent == Some(dirs[top]) {
+                    break;
+                }
+                subtree_end[top] = pos;
+                open.pop();
+            }
+            open.push(pos);
+        }
+
+        // exact[i]: sums below target reachable from position i
+        // over[i]: cheapest sum >= target reachable from position i
+        let mut exact = vec![BitSet::new(target); n + 1];
+        exact[n].set(0);
+        let mut over: Vec<Option<(usize, Choice)>> = vec![None; n + 1];
+        for i in (0..n).rev() {
+            let size = sizes[i];
+            let j = subtree_end[i];
+            let (skip, rest) = exact.split_at_mut(i + 1);
+            skip[i] = rest[0].clone();
+            if size < target {
+                skip[i].or_shifted(&rest[j - i - 1], size);
+            }
+
+            let candidates = [
+                over[i + 1].map(|(sum, _)| (sum, Choice::Skip)),
+                over[j].map(|(sum, _)| (sum + size, Choice::Take)),
+                rest[j - i - 1].first_from(target.saturating_sub(size))
+                    .map(|b| (b + size, Choice::TakeExact(b))),
+            ];
+            over[i] = candidates.into_iter().flatten().min_by_key(|(sum, _)| *sum);
+        }
+
+        let mut plan = DeletionPlan { to_delete, dirs: Vec::new(), freed: 0, granularity };
+        // Rounding sizes down can lose every plan but deleting everything
+        if over[0].is_none() {
+            plan.dirs.push(dirs[0]);
+            plan.freed = self.root().size;
+            return Some(plan);
+        }
+        let mut i = 0;
+        let mut exact_target = None;
+        while i < n {
+            match exact_target {
+                // Rebuilding an exact sum below target
+                Some(0) => break,
+                Some(t) => {
+                    if exact[i + 1].get(t) {
+                        i += 1;
+                    } else {
+                        plan.dirs.push(dirs[i]);
+                        exact_target = Some(t - sizes[i]);
+                        i = subtree_end[i];
+                    }
+                },
+                None => match over[i].unwrap().1 {
+                    Choice::Skip => i += 1,
+                    Choice::Take => {
+                        plan.dirs.push(dirs[i]);
+                        i = subtree_end[i];
+                    },
+                    Choice::TakeExact(b) => {
+                        plan.dirs.push(dirs[i]);
+                        exact_target = Some(b);
+                        i = subtree_end[i];
+                    }
+                },
+            }
+        }
+        plan.freed = plan.dirs.iter().map(|&id| self.nodes[id].size).sum();
+        Some(plan)
+    }
+}
+
+fn print_plan(vfs: &Vfs, total: usize, required: usize) {
+    let to_delete = vfs.space_to_free(total, required);
+    println!("Used: {} - Total: {} - Required: {} - To delete: {}", vfs.root().size, total, required, to_delete);
+    if to_delete == 0 {
+        println!("Nothing to delete");
+        return;
+    }
+
+    println!("Single deletions:");
+    vfs.single_deletions(to_delete).iter().enumerate().for_each(|(rank, &id)| {
+        println!("  {}. {}\t{}", rank + 1, vfs.nodes[id].size, vfs.path(id));
+    });
+
+    match vfs.plan_deletion(to_delete) {
+        Some(plan) => {
+            println!("Best plan: delete {} director{}, freeing {} ({} more than needed)",
+                     plan.dirs.len(), if plan.dirs.len() == 1 { "y" } else { "ies" },
+                     plan.freed, plan.freed - plan.to_delete);
+            plan.dirs.iter().for_each(|&id| println!("  {}\t{}", vfs.nodes[id].size, vfs.path(id)));
+            if plan.granularity > 1 {
+                println!("Approximate plan: sizes counted in units of {}", plan.granularity);
+            }
         },
-        _ => {
-            let mut it = line.split_whitespace();
-            let size = it.next().unwrap().parse::<usize>().unwrap();
-            let name = String::from(it.next().unwrap());
-            FILE {
-                size,
-                name,
+        None => println!("Not enough space even deleting everything"),
+    }
+}
+
+fn human_size(size: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}{}", size, UNITS[0])
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+// Shell-style pattern: * matches any sequence, ? any single char
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last * and the name position it is matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            backtrack = Some((p, n));
+        } else if let Some((bp, bn)) = backtrack {
+            p = bp;
+            n = bn + 1;
+            backtrack = Some((bp, bn + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+// Node listed by a report, with its depth below the root
+struct Entry {
+    id: usize,
+    depth: usize,
+}
+
+enum Query {
+    Tree,
+    Du { max_depth: Option<usize> },
+    Find { name: Option<String>, min_size: usize, max_size: usize, kind: Option<bool> },
+    Top { n: usize },
+}
+
+impl Query {
+    fn run(&self, vfs: &Vfs) -> Vec<Entry> {
+        let entry = |(id, depth)| Entry { id, depth };
+        match self {
+            Query::Tree => vfs.preorder().into_iter().map(entry).collect(),
+            Query::Du { max_depth } => vfs.postorder().into_iter()
+                .filter(|&(id, depth)| vfs.nodes[id].dir && max_depth.is_none_or(|max| depth <= max))
+                .map(entry)
+                .collect(),
+            Query::Find { name, min_size, max_size, kind } => vfs.preorder().into_iter()
+                .filter(|&(id, _)| {
+                    let node = &vfs.nodes[id];
+                    name.as_ref().is_none_or(|pattern| glob_match(pattern, &node.name)) &&
+                        *min_size <= node.size && node.size <= *max_size &&
+                        kind.is_none_or(|dir| node.dir == dir)
+                })
+                .map(entry)
+                .collect(),
+            Query::Top { n } => {
+                let mut dirs: Vec<(usize, usize)> = vfs.preorder().into_iter()
+                    .filter(|&(id, _)| vfs.nodes[id].dir)
+                    .collect();
+                dirs.sort_by(|a, b| vfs.nodes[b.0].size.cmp(&vfs.nodes[a.0].size));
+                dirs.into_iter().take(*n).map(entry).collect()
+            }
+        }
+    }
+
+    fn print_text(&self, vfs: &Vfs, entries: &[Entry]) {
+        for e in entries {
+            let node = &vfs.nodes[e.id];
+            match self {
+                Query::Tree => {
+                    let kind = if node.dir { "dir" } else { "file" };
+                    let name = if e.id == Vfs::ROOT { "/" } else { node.name.as_str() };
+                    println!("{}- {} ({}, {})", "  ".repeat(e.depth), name, kind, human_size(node.size));
+                },
+                Query::Find { .. } => println!("{}\t{}", human_size(node.size), vfs.path(e.id)),
+                Query::Du { .. } | Query::Top { .. } => println!("{}\t{}", node.size, vfs.path(e.id)),
             }
         }
     }
+
+    fn print_json(&self, vfs: &Vfs, entries: &[Entry]) {
+        println!("[");
+        entries.iter().enumerate().for_each(|(idx, e)| {
+            let node = &vfs.nodes[e.id];
+            let kind = if node.dir { "dir" } else { "file" };
+            let sep = if idx + 1 < entries.len() { "," } else { "" };
+            println!("  {{\"path\": {}, \"type\": \"{}\", \"size\": {}, \"depth\": {}}}{}",
+                     json_string(&vfs.path(e.id)), kind, node.size, e.depth, sep);
+        });
+        println!("]");
+    }
+}
+
+// Parses "tree", "du [-d N]", "find [-name GLOB] [-size MIN-MAX] [-type f|d]"
+// or "top N"
+fn parse_query(args: &[String]) -> Query {
+    let flag = |name: &str| {
+        args.iter().position(|a| a == name).map(|idx| args.get(idx + 1).expect("Missing argument value").as_str())
+    };
+    match args[0].as_str() {
+        "tree" => Query::Tree,
+        "du" => Query::Du {
+            max_depth: flag("-d").map(|d| d.parse::<usize>().expect("Invalid depth")),
+        },
+        "find" => {
+            let (min_size, max_size) = match flag("-size") {
+                Some(range) => {
+                    let (min, max) = range.split_once('-').expect("Size range must be MIN-MAX");
+                    (min.parse::<usize>().unwrap_or(0), max.parse::<usize>().unwrap_or(usize::MAX))
+                },
+                None => (0, usize::MAX),
+            };
+            let kind = flag("-type").map(|t| match t {
+                "d" => true,
+                "f" => false,
+                _ => panic!("Unknown type {t}"),
+            });
+            Query::Find { name: flag("-name").map(String::from), min_size, max_size, kind }
+        },
+        "top" => Query::Top {
+            n: args.get(1).map_or(10, |n| n.parse::<usize>().expect("Invalid count")),
+        },
+        cmd => panic!("Unknown command {cmd}"),
+    }
+}
+
+fn parse_line(line: &str) -> Cmd {
+    if let Some(path) = line.strip_prefix("$ cd ") {
+        Cd { path: String::from(path) }
+    } else if line == "$ ls" {
+        Ls
+    } else if let Some(name) = line.strip_prefix("dir ") {
+        Dir { name: String::from(name) }
+    } else {
+        let mut it = line.split_whitespace();
+        let size = it.next().unwrap().parse::<usize>().unwrap();
+        let name = String::from(it.next().unwrap());
+        FileEntry {
+            size,
+            name,
+        }
+    }
 }
 
 fn parse_input() -> impl Iterator<Item = Cmd> {
@@ -119,25 +627,189 @@ fn parse_input() -> impl Iterator<Item = Cmd> {
     lines.map(|line| parse_line(&line.unwrap()))
 }
 
+// Usage: day_7 [tree | du [-d N] | find [-name GLOB] [-size MIN-MAX] [-type f|d] | top [N]] [--json]
+//        day_7 plan [-total N] [-required N]
 fn main() {
-    let mut input_iter = parse_input();
-    let mut root = Node::new_dir("/");
-    root.parse(&mut input_iter);
-    root.print_visit();
+    let args: Vec<String> = env::args().skip(1).filter(|a| a != "--json").collect();
+    let json = env::args().any(|a| a == "--json");
+    let vfs = Vfs::parse(parse_input());
+
+    if args.first().is_some_and(|a| a == "plan") {
+        let flag = |name: &str, default: usize| {
+            args.iter().position(|a| a == name)
+                .map_or(default, |idx| args.get(idx + 1).and_then(|v| v.parse::<usize>().ok()).expect("Invalid size"))
+        };
+        print_plan(&vfs, flag("-total", 70000000), flag("-required", 30000000));
+        return;
+    }
+
+    if !args.is_empty() {
+        let query = parse_query(&args);
+        let entries = query.run(&vfs);
+        if json {
+            query.print_json(&vfs, &entries);
+        } else {
+            query.print_text(&vfs, &entries);
+        }
+        return;
+    }
 
     // Part 1
     let mut part1_res: usize = 0;
-    root.part1_visit(&mut part1_res);
+    vfs.part1_visit(&mut part1_res);
     println!("Part 1: {}", part1_res);
 
     // Part 2
     let disk_space: usize = 70000000;
     let required_space: usize = 30000000;
     let max_occupied: usize = disk_space - required_space;
-    let currently_occupied: usize = root.size;
-    let to_delete = currently_occupied - max_occupied;
+    let currently_occupied: usize = vfs.root().size;
+    let to_delete = currently_occupied.saturating_sub(max_occupied);
     println!("Current size: {} - Max: {} - To delete: {}", currently_occupied, max_occupied, to_delete);
-    let mut part2_res: usize = root.size;
-    root.part2_visit(to_delete, &mut part2_res);
+    let mut part2_res: usize = vfs.root().size;
+    vfs.part2_visit(to_delete, &mut part2_res);
     println!("Part 2: {}", part2_res);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vfs(lines: &[&str]) -> Vfs {
+        Vfs::parse(lines.iter().map(|l| parse_line(l)))
+    }
+
+    fn is_ancestor(vfs: &Vfs, a: usize, mut b: usize) -> bool {
+        while let Some(parent) = vfs.nodes[b].parent {
+            if parent == a {
+                return true;
+            }
+            b = parent;
+        }
+        false
+    }
+
+    fn check_plan(vfs: &Vfs, plan: &DeletionPlan) {
+        for &a in &plan.dirs {
+            assert!(vfs.nodes[a].dir);
+            assert!(plan.dirs.iter().all(|&b| !is_ancestor(vfs, a, b)), "nested directories in the plan");
+        }
+        assert_eq!(plan.freed, plan.dirs.iter().map(|&id| vfs.nodes[id].size).sum::<usize>());
+        assert!(plan.freed >= plan.to_delete);
+    }
+
+    // Smallest total size >= to_delete of a set of non-nested directories
+    fn brute_force(vfs: &Vfs, to_delete: usize) -> Option<usize> {
+        let dirs: Vec<usize> = (0..vfs.nodes.len()).filter(|&id| vfs.nodes[id].dir).collect();
+        (0u32..1 << dirs.len()).filter_map(|mask| {
+            let chosen: Vec<usize> = (0..dirs.len()).filter(|i| mask & (1 << i) != 0).map(|i| dirs[i]).collect();
+            let nested = chosen.iter().any(|&a| chosen.iter().any(|&b| is_ancestor(vfs, a, b)));
+            let sum = chosen.iter().map(|&id| vfs.nodes[id].size).sum::<usize>();
+            (!nested && sum >= to_delete).then_some(sum)
+        }).min()
+    }
+
+    const NESTED: &[&str] = &[
+        "$ cd /", "$ ls", "dir a", "dir b", "5 f",
+        "$ cd a", "$ ls", "dir x", "10 g",
+        "$ cd x", "$ ls", "40 h",
+        "$ cd /b", "$ ls", "45 i",
+    ];
+
+    #[test]
+    fn nothing_to_delete() {
+        let plan = vfs(NESTED).plan_deletion(0).unwrap();
+        assert!(plan.dirs.is_empty());
+        assert_eq!(plan.freed, 0);
+    }
+
+    #[test]
+    fn not_enough_space() {
+        let vfs = vfs(NESTED);
+        assert_eq!(vfs.root().size, 100);
+        assert!(vfs.plan_deletion(101).is_none());
+        let plan = vfs.plan_deletion(100).unwrap();
+        check_plan(&vfs, &plan);
+        assert_eq!(plan.freed, 100);
+    }
+
+    #[test]
+    fn nested_candidates() {
+        // /a (50) and /a/x (40) cannot be deleted together: /a/x + /b is the best
+        let vfs = vfs(NESTED);
+        let plan = vfs.plan_deletion(85).unwrap();
+        check_plan(&vfs, &plan);
+        assert_eq!(plan.freed, 85);
+        assert_eq!(plan.dirs.len(), 2);
+        assert_eq!(plan.granularity, 1);
+    }
+
+    #[test]
+    fn exact_fit() {
+        let vfs = vfs(&[
+            "$ cd /", "$ ls", "dir a", "dir b", "dir c",
+            "$ cd a", "$ ls", "30 f",
+            "$ cd ../b", "$ ls", "45 f",
+            "$ cd ../c", "$ ls", "20 f",
+        ]);
+        let plan = vfs.plan_deletion(50).unwrap();
+        check_plan(&vfs, &plan);
+        assert_eq!(plan.freed, 50);
+        let plan = vfs.plan_deletion(46).unwrap();
+        check_plan(&vfs, &plan);
+        assert_eq!(plan.freed, 50);
+    }
+
+    // Random trees of up to 12 directories
+    fn random_vfs(seed: &mut u64) -> Vfs {
+        let mut next = |n: u64| {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 7;
+            *seed ^= *seed << 17;
+            *seed % n
+        };
+        let mut lines = vec!["$ cd /".to_string(), "$ ls".to_string()];
+        let mut paths = vec![String::from("/")];
+        for i in 0..next(12) {
+            let parent = paths[next(paths.len() as u64) as usize].clone();
+            let path = format!("{}d{}/", parent, i);
+            lines.extend([format!("$ cd {parent}"), "$ ls".to_string(), format!("dir d{i}")]);
+            lines.extend([format!("$ cd {path}"), "$ ls".to_string(), format!("{} f", next(100) + 1)]);
+            paths.push(path);
+        }
+        let lines: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+        vfs(&lines)
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut seed = 0x2545F4914F6CDD1D;
+        for _ in 0..300 {
+            let vfs = random_vfs(&mut seed);
+            for to_delete in [1, 17, 60, 150, vfs.root().size, vfs.root().size + 1] {
+                let plan = vfs.plan_deletion(to_delete);
+                assert_eq!(plan.as_ref().map(|p| p.freed), brute_force(&vfs, to_delete));
+                if let Some(plan) = plan {
+                    check_plan(&vfs, &plan);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn approximate_plan() {
+        let mut seed = 0x9E3779B97F4A7C15;
+        for _ in 0..300 {
+            let vfs = random_vfs(&mut seed);
+            for to_delete in [1, 60, 150, vfs.root().size] {
+                let best = brute_force(&vfs, to_delete);
+                let plan = vfs.plan_deletion_within(to_delete, 64);
+                assert_eq!(plan.is_some(), best.is_some());
+                if let (Some(plan), Some(best)) = (plan, best) {
+                    check_plan(&vfs, &plan);
+                    assert!(plan.freed >= best);
+                }
+            }
+        }
+    }
+}
@@ -1,34 +1,44 @@
-use std::fmt::{Display, Formatter};
+use std::collections::HashSet;
+use std::env;
 use std::fs::File;
-use std::hash::Hash;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, BufWriter, Write};
+
+// Directions a tree can be seen from, as bits of Tree.visible_from
+const FROM_LEFT: u8 = 1;
+const FROM_RIGHT: u8 = 2;
+const FROM_TOP: u8 = 4;
+const FROM_BOTTOM: u8 = 8;
 
 #[derive(Copy, Clone)]
 struct Tree {
     height: u8,
-    visible: bool,
+    visible_from: u8,
 }
 
 impl Tree {
     fn new(height: u8) -> Self {
         Self {
             height,
-            visible: false
+            visible_from: 0
         }
     }
+
+    fn visible(&self) -> bool {
+        self.visible_from != 0
+    }
 }
 
-fn print_field(field: &Vec<Vec<Tree>>) {
+fn print_field(field: &[Vec<Tree>]) {
     field.iter().for_each(|line| {
         line.iter().for_each(|t| print!("{}", t.height));
         println!();
     })
 }
 
-fn parse_line(line: &String) -> Vec<Tree> {
+fn parse_line(line: &str) -> Vec<Tree> {
     line.chars()
         .map(|c| c.to_string().parse::<u8>().unwrap())
-        .map(|h| Tree::new(h))
+        .map(Tree::new)
         .collect()
 }
 
@@ -41,15 +51,15 @@ fn parse_input() -> Vec<Vec<Tree>> {
     }).collect()
 }
 
-fn compute_visibility(field: &mut Vec<Vec<Tree>>) {
+fn compute_visibility(field: &mut [Vec<Tree>]) {
     let height = field.len();
     let width = field[0].len();
 
     // Returns true if max becomes 9
-    fn update_visibility(field: &mut Vec<Vec<Tree>>, i: usize, j: usize, max: &mut i8) -> bool {
+    fn update_visibility(field: &mut [Vec<Tree>], i: usize, j: usize, dir: u8, max: &mut i8) -> bool {
         if field[i][j].height as i8 > *max {
             *max = field[i][j].height as i8;
-            field[i][j].visible = true;
+            field[i][j].visible_from |= dir;
             *max == 9
         } else {
             false
@@ -60,7 +70,7 @@ fn compute_visibility(field: &mut Vec<Vec<Tree>>) {
     for i in 0..height {
         let mut max: i8 = -1;
         for j in 0..width {
-            if update_visibility(field, i, j, &mut max) { break; }
+            if update_visibility(field, i, j, FROM_LEFT, &mut max) { break; }
         }
     }
 
@@ -68,7 +78,7 @@ fn compute_visibility(field: &mut Vec<Vec<Tree>>) {
     for i in 0..height {
         let mut max: i8 = -1;
         for j in (0..width).rev() {
-            if update_visibility(field, i, j, &mut max) { break; }
+            if update_visibility(field, i, j, FROM_RIGHT, &mut max) { break; }
         }
     }
 
@@ -76,7 +86,7 @@ fn compute_visibility(field: &mut Vec<Vec<Tree>>) {
     for j in 0..width {
         let mut max: i8 = -1;
         for i in 0..height {
-            if update_visibility(field, i, j, &mut max) { break; }
+            if update_visibility(field, i, j, FROM_TOP, &mut max) { break; }
         }
     }
 
@@ -84,71 +94,317 @@ fn compute_visibility(field: &mut Vec<Vec<Tree>>) {
     for j in 0..width {
         let mut max: i8 = -1;
         for i in (0..height).rev() {
-            if update_visibility(field, i, j, &mut max) { break; }
+            if update_visibility(field, i, j, FROM_BOTTOM, &mut max) { break; }
         }
     }
 }
 
-fn compute_num_visible(field: &Vec<Vec<Tree>>) -> usize {
+fn compute_num_visible(field: &[Vec<Tree>]) -> usize {
     field.iter().map(|line| {
-        line.iter().filter(|t| t.visible).count()
+        line.iter().filter(|t| t.visible()).count()
     }).sum()
 }
 
-fn compute_scenic_score(field: &Vec<Vec<Tree>>) -> usize {
-    fn compute_scenic_score_for(field: &Vec<Vec<Tree>>, i: usize, j: usize, height: usize, width: usize) -> usize {
-        let mut top = 0;
-        for k in (0..i).rev() {
-            top += 1;
-            if field[k][j].height >= field[i][j].height {
-                break;
-            }
+// Viewing distance towards the start of the line from every tree of the line.
+// The stack holds the trees that can still block the view, tallest at the
+// bottom, so every tree is pushed and popped at most once.
+fn viewing_distances(line: &[u8]) -> Vec<usize> {
+    let mut stack: Vec<usize> = Vec::new();
+    line.iter().enumerate().map(|(k, &h)| {
+        while stack.last().is_some_and(|&top| line[top] < h) {
+            stack.pop();
         }
+        let dist = match stack.last() {
+            Some(&top) => k - top,
+            None => k,
+        };
+        stack.push(k);
+        dist
+    }).collect()
+}
+
+// Scenic score of every tree
+fn compute_scenic_scores(field: &[Vec<Tree>]) -> Vec<Vec<usize>> {
+    let height = field.len();
+    let width = field[0].len();
+    let mut scores = vec![vec![1; width]; height];
+
+    for (i, row) in field.iter().enumerate() {
+        let mut line: Vec<u8> = row.iter().map(|t| t.height).collect();
+        // From left
+        viewing_distances(&line).iter().enumerate().for_each(|(j, d)| scores[i][j] *= d);
+        // From right
+        line.reverse();
+        viewing_distances(&line).iter().enumerate().for_each(|(j, d)| scores[i][width - 1 - j] *= d);
+    }
+
+    for j in 0..width {
+        let mut line: Vec<u8> = field.iter().map(|row| row[j].height).collect();
+        // From top
+        viewing_distances(&line).iter().enumerate().for_each(|(i, d)| scores[i][j] *= d);
+        // From bottom
+        line.reverse();
+        viewing_distances(&line).iter().enumerate().for_each(|(i, d)| scores[height - 1 - i][j] *= d);
+    }
+
+    scores
+}
+
+fn compute_scenic_score(scores: &[Vec<usize>]) -> usize {
+    scores.iter().flatten().copied().max().unwrap_or(0)
+}
 
-        let mut bottom = 0;
-        for k in i+1..height {
-            bottom += 1;
-            if field[k][j].height >= field[i][j].height {
-                break;
+// Position of the tree with the highest score
+fn best_tree(scores: &[Vec<usize>]) -> (usize, usize) {
+    let mut best = (0, 0);
+    for (i, row) in scores.iter().enumerate() {
+        for (j, &score) in row.iter().enumerate() {
+            if score > scores[best.0][best.1] {
+                best = (i, j);
             }
         }
+    }
+    best
+}
 
-        let mut left = 0;
-        for k in (0..j).rev() {
-            left += 1;
-            if field[i][k].height >= field[i][j].height {
-                break;
+// Marks the best tree in ASCII renderings
+const BEST_MARK: char = '@';
+const SHADES: &[u8] = b" .:-=+*#%";
+
+// Directions each tree is visible from, as a hex digit of its bitmask
+// (1 left, 2 right, 4 top, 8 bottom), or '.' if hidden
+fn render_directions(field: &[Vec<Tree>], best: (usize, usize)) -> Vec<String> {
+    field.iter().enumerate().map(|(i, row)| {
+        row.iter().enumerate().map(|(j, t)| {
+            if (i, j) == best { BEST_MARK }
+            else if t.visible() { char::from_digit(t.visible_from as u32, 16).unwrap().to_ascii_uppercase() }
+            else { '.' }
+        }).collect()
+    }).collect()
+}
+
+// Visible trees shaded by the number of directions they can be seen from
+fn render_visibility(field: &[Vec<Tree>], best: (usize, usize)) -> Vec<String> {
+    field.iter().enumerate().map(|(i, row)| {
+        row.iter().enumerate().map(|(j, t)| {
+            if (i, j) == best { BEST_MARK }
+            else { SHADES[t.visible_from.count_ones() as usize * 2] as char }
+        }).collect()
+    }).collect()
+}
+
+// Scores are spread over several orders of magnitude, so shading (and the
+// heatmap) uses a log scale
+fn intensity(score: usize, max: usize) -> f64 {
+    if max == 0 { 0.0 } else { (score as f64).ln_1p() / (max as f64).ln_1p() }
+}
+
+fn render_scenic(scores: &[Vec<usize>], best: (usize, usize)) -> Vec<String> {
+    let max = compute_scenic_score(scores);
+    scores.iter().enumerate().map(|(i, row)| {
+        row.iter().enumerate().map(|(j, &score)| {
+            if (i, j) == best { BEST_MARK }
+            else { SHADES[(intensity(score, max) * (SHADES.len() - 1) as f64).round() as usize] as char }
+        }).collect()
+    }).collect()
+}
+
+// Black - red - yellow - white color scale, for t in [0, 1]
+fn heat_color(t: f64) -> [u8; 3] {
+    let channel = |offset: f64| ((3.0 * t - offset).clamp(0.0, 1.0) * 255.0) as u8;
+    [channel(0.0), channel(1.0), channel(2.0)]
+}
+
+const BEST_COLOR: [u8; 3] = [0, 160, 255];
+
+// Binary PPM image with a scale x scale square per tree
+fn write_ppm(path: &str, colors: &[Vec<[u8; 3]>], scale: usize) -> io::Result<()> {
+    let height = colors.len();
+    let width = colors.first().map_or(0, |row| row.len());
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P6\n{} {}\n255\n", width * scale, height * scale)?;
+    for row in colors {
+        for _ in 0..scale {
+            for color in row {
+                for _ in 0..scale {
+                    out.write_all(color)?;
+                }
             }
         }
+    }
+    out.flush()
+}
 
-        let mut right = 0;
-        for k in j+1..width {
-            right += 1;
-            if field[i][k].height >= field[i][j].height {
-                break;
-            }
+fn visibility_colors(field: &[Vec<Tree>], best: (usize, usize)) -> Vec<Vec<[u8; 3]>> {
+    field.iter().enumerate().map(|(i, row)| {
+        row.iter().enumerate().map(|(j, t)| {
+            if (i, j) == best { BEST_COLOR }
+            else { heat_color(t.visible_from.count_ones() as f64 / 4.0) }
+        }).collect()
+    }).collect()
+}
+
+fn scenic_colors(scores: &[Vec<usize>], best: (usize, usize)) -> Vec<Vec<[u8; 3]>> {
+    let max = compute_scenic_score(scores);
+    scores.iter().enumerate().map(|(i, row)| {
+        row.iter().enumerate().map(|(j, &score)| {
+            if (i, j) == best { BEST_COLOR }
+            else { heat_color(intensity(score, max)) }
+        }).collect()
+    }).collect()
+}
+
+// Cells crossed by the ray starting at the center of cell from with direction
+// (d_row, d_col), in order and without from itself, while t <= max_t (t is 1
+// after one direction vector). Rays through a corner step diagonally.
+fn cells_along(from: (usize, usize), dir: (f64, f64), max_t: f64, height: usize, width: usize) -> Vec<(usize, usize)> {
+    const EPSILON: f64 = 1e-9;
+    // Step, t of the first cell boundary and t between boundaries on one axis
+    let axis = |d: f64| {
+        if d > 0.0 { (1, 0.5 / d, 1.0 / d) }
+        else if d < 0.0 { (-1, -0.5 / d, -1.0 / d) }
+        else { (0, f64::INFINITY, f64::INFINITY) }
+    };
+    let (step_r, mut next_r, delta_r) = axis(dir.0);
+    let (step_c, mut next_c, delta_c) = axis(dir.1);
+    let (mut r, mut c) = (from.0 as i64, from.1 as i64);
+
+    let mut res = Vec::new();
+    loop {
+        let t = next_r.min(next_c);
+        if t > max_t + EPSILON {
+            break;
         }
+        if next_r <= next_c + EPSILON {
+            r += step_r;
+            next_r += delta_r;
+        }
+        if next_c <= t + EPSILON {
+            c += step_c;
+            next_c += delta_c;
+        }
+        if r < 0 || c < 0 || r >= height as i64 || c >= width as i64 {
+            break;
+        }
+        res.push((r as usize, c as usize));
+    }
+    res
+}
 
-        top * bottom * left * right
+// Line of sight model: an observer stands at the center of a cell with the
+// eye at a given height, and sees the top of a tree if its elevation angle
+// is greater than the one of every tree the sightline passes over before it.
+// The tallest of those (in elevation) is the occluder of a hidden tree.
+struct Observer {
+    pos: (usize, usize),
+    eye: f64,
+}
+
+#[derive(Debug)]
+enum Sight {
+    Visible,
+    HiddenBy((usize, usize)),
+}
+
+impl Observer {
+    fn elevation(&self, field: &[Vec<Tree>], (i, j): (usize, usize)) -> f64 {
+        let dist = ((i as f64 - self.pos.0 as f64).powi(2) + (j as f64 - self.pos.1 as f64).powi(2)).sqrt();
+        (field[i][j].height as f64 - self.eye) / dist
     }
 
-    let height = field.len();
-    let width = field[0].len();
-    let mut max = 0;
-    for i in 0..height {
-        for j in 0..width {
-            let scenic_score = compute_scenic_score_for(field, i, j, height, width);
-            if scenic_score > max {
-                max = scenic_score
+    // Visibility of the trees along the cells crossed, nearest first
+    fn scan(&self, field: &[Vec<Tree>], cells: &[(usize, usize)]) -> Vec<Sight> {
+        let mut horizon: Option<(f64, (usize, usize))> = None;
+        cells.iter().map(|&cell| {
+            let elevation = self.elevation(field, cell);
+            match horizon {
+                Some((max, occluder)) if elevation <= max => Sight::HiddenBy(occluder),
+                _ => {
+                    horizon = Some((elevation, cell));
+                    Sight::Visible
+                }
             }
-        }
+        }).collect()
     }
-    max
+
+    // Visibility of every tree of the field, None for the observer's own cell
+    fn line_of_sight(&self, field: &[Vec<Tree>]) -> Vec<Vec<Option<Sight>>> {
+        let height = field.len();
+        let width = field[0].len();
+        (0..height).map(|i| {
+            (0..width).map(|j| {
+                if (i, j) == self.pos {
+                    return None;
+                }
+                let dir = (i as f64 - self.pos.0 as f64, j as f64 - self.pos.1 as f64);
+                let cells = cells_along(self.pos, dir, 1.0, height, width);
+                // The sightline ends at the target, the last cell crossed
+                self.scan(field, &cells).pop()
+            }).collect()
+        }).collect()
+    }
+
+    // Trees along the ray with the given angle, in degrees counterclockwise
+    // from the direction of growing columns
+    fn ray(&self, field: &[Vec<Tree>], angle: f64) -> Vec<((usize, usize), Sight)> {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let cells = cells_along(self.pos, (-sin, cos), f64::INFINITY, field.len(), field[0].len());
+        let sights = self.scan(field, &cells);
+        cells.into_iter().zip(sights).collect()
+    }
+}
+
+// Observer 'O', visible trees as their height, hidden ones '.', and trees
+// hiding at least another one '#'
+fn render_line_of_sight(field: &[Vec<Tree>], sights: &[Vec<Option<Sight>>]) -> Vec<String> {
+    let occluders: HashSet<(usize, usize)> = sights.iter().flatten()
+        .filter_map(|s| match s {
+            Some(Sight::HiddenBy(pos)) => Some(*pos),
+            _ => None,
+        })
+        .collect();
+    sights.iter().enumerate().map(|(i, row)| {
+        row.iter().enumerate().map(|(j, sight)| {
+            match sight {
+                None => 'O',
+                Some(_) if occluders.contains(&(i, j)) => '#',
+                Some(Sight::Visible) => char::from_digit(field[i][j].height as u32, 10).unwrap(),
+                Some(Sight::HiddenBy(_)) => '.',
+            }
+        }).collect()
+    }).collect()
+}
+
+// Forest of the given size with pseudo-random heights
+fn generate_field(width: usize, height: usize, seed: u64) -> Vec<Vec<Tree>> {
+    // xorshift64
+    let mut state = seed.max(1);
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..height).map(|_| {
+        (0..width).map(|_| Tree::new((next() % 10) as u8)).collect()
+    }).collect()
 }
 
+// Usage: day_8 [--generate WIDTH HEIGHT [SEED]] [--render directions|visibility|scenic]
+//              [--ppm visibility|scenic FILE [SCALE]] [--observer ROW COL EYE_HEIGHT [--ray ANGLE]]
 fn main() {
-    let mut field = parse_input();
-    print_field(&field);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut field = match args.iter().position(|a| a == "--generate") {
+        Some(idx) => {
+            let num = |k: usize| args.get(idx + k).map(|v| v.parse::<usize>().expect("Invalid number"));
+            generate_field(num(1).expect("Missing width"), num(2).expect("Missing height"), num(3).unwrap_or(1) as u64)
+        },
+        None => {
+            let field = parse_input();
+            print_field(&field);
+            field
+        }
+    };
 
     // Part 1
     compute_visibility(&mut field);
@@ -156,6 +412,57 @@ fn main() {
     println!("Part 1: {}", num_visible);
 
     // Part 2
-    let max_scenic_score = compute_scenic_score(&mut field);
+    let scores = compute_scenic_scores(&field);
+    let max_scenic_score = compute_scenic_score(&scores);
     println!("Part 2: {}", max_scenic_score);
+
+    let best = best_tree(&scores);
+    if let Some(idx) = args.iter().position(|a| a == "--render") {
+        println!("Best tree ({}) at row {}, column {}", BEST_MARK, best.0, best.1);
+        let lines = match args.get(idx + 1).map(|a| a.as_str()) {
+            Some("directions") => render_directions(&field, best),
+            Some("visibility") => render_visibility(&field, best),
+            Some("scenic") => render_scenic(&scores, best),
+            m => panic!("Unknown rendering {:?}", m),
+        };
+        lines.iter().for_each(|line| println!("{line}"));
+    }
+    if let Some(idx) = args.iter().position(|a| a == "--observer") {
+        let arg = |k: usize| args.get(idx + k).expect("Missing observer argument");
+        let coord = |k: usize| arg(k).parse::<usize>().expect("Row and column must be non-negative integers");
+        let eye = arg(3).parse::<f64>().expect("Invalid eye height");
+        let observer = Observer { pos: (coord(1), coord(2)), eye };
+        assert!(observer.pos.0 < field.len() && observer.pos.1 < field[0].len(), "Observer outside the forest");
+
+        match args.iter().position(|a| a == "--ray") {
+            Some(ray_idx) => {
+                let angle = args.get(ray_idx + 1).map(|v| v.parse::<f64>().expect("Invalid angle")).expect("Missing angle");
+                for ((i, j), sight) in observer.ray(&field, angle) {
+                    match sight {
+                        Sight::Visible => println!("({}, {}) height {}: visible", i, j, field[i][j].height),
+                        Sight::HiddenBy((oi, oj)) => println!("({}, {}) height {}: hidden by ({}, {})", i, j, field[i][j].height, oi, oj),
+                    }
+                }
+            },
+            None => {
+                let sights = observer.line_of_sight(&field);
+                let visible = sights.iter().flatten().filter(|s| matches!(s, Some(Sight::Visible))).count();
+                println!("Visible from ({}, {}) at height {}: {}", observer.pos.0, observer.pos.1, observer.eye, visible);
+                render_line_of_sight(&field, &sights).iter().for_each(|line| println!("{line}"));
+            }
+        }
+    }
+    if let Some(idx) = args.iter().position(|a| a == "--ppm") {
+        let path = args.get(idx + 2).expect("Missing output file");
+        let scale = args.get(idx + 3).map_or(4, |s| s.parse::<usize>().expect("Invalid scale"));
+        let colors = match args.get(idx + 1).map(|a| a.as_str()) {
+            Some("visibility") => visibility_colors(&field, best),
+            Some("scenic") => scenic_colors(&scores, best),
+            m => panic!("Unknown heatmap {:?}", m),
+        };
+        match write_ppm(path, &colors, scale) {
+            Ok(()) => println!("Heatmap written to {path}"),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
 }
@@ -1,58 +1,280 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::{self, BufRead};
-use crate::Move::{R, L, U, D};
+use std::io::{self, BufRead, BufWriter, Write};
 
-#[derive(Debug)]
-enum Move {
-    R, L, U, D
+// Head move: one step along any combination of the x (R/L), y (U/D) and
+// z (F/B) axes, so UR is a diagonal and DLF a 3D diagonal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Move {
+    dx: i32,
+    dy: i32,
+    dz: i32,
+}
+
+impl Move {
+    fn parse(s: &str) -> Move {
+        let mut m = Move { dx: 0, dy: 0, dz: 0 };
+        for c in s.chars() {
+            let (axis, step) = match c {
+                'R' => (&mut m.dx, 1),
+                'L' => (&mut m.dx, -1),
+                'U' => (&mut m.dy, 1),
+                'D' => (&mut m.dy, -1),
+                'F' => (&mut m.dz, 1),
+                'B' => (&mut m.dz, -1),
+                _ => panic!("Unknown direction {s}")
+            };
+            assert!(*axis == 0, "Direction {s} moves twice along the same axis");
+            *axis = step;
+        }
+        assert!(m != Move { dx: 0, dy: 0, dz: 0 }, "Empty direction");
+        m
+    }
+
+    // Move between two positions at most one step apart, packed in a byte
+    fn encode(from: &Pos, to: &Pos) -> u8 {
+        ((to.x - from.x + 1) * 9 + (to.y - from.y + 1) * 3 + (to.z - from.z + 1)) as u8
+    }
+
+    fn decode(code: u8) -> Move {
+        let code = code as i32;
+        Move { dx: code / 9 - 1, dy: code / 3 % 3 - 1, dz: code % 3 - 1 }
+    }
+}
+
+impl Display for Move {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let letter = |d: i32, pos: char, neg: char| match d {
+            1 => pos.to_string(),
+            -1 => neg.to_string(),
+            _ => String::new(),
+        };
+        write!(f, "{}{}{}", letter(self.dy, 'U', 'D'), letter(self.dx, 'R', 'L'), letter(self.dz, 'F', 'B'))
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 struct Pos {
     x: i32,
     y: i32,
+    z: i32,
 }
 
 impl Pos {
     fn apply_move(&self, m: &Move) -> Pos {
-        match m {
-            R => Pos { x: self.x+1, y: self.y },
-            L => Pos { x: self.x-1, y: self.y },
-            U => Pos { x: self.x, y: self.y+1 },
-            D => Pos { x: self.x, y: self.y-1 },
-        }
+        self.shift(m, 1)
+    }
+
+    // Position after k steps of m
+    fn shift(&self, m: &Move, k: i32) -> Pos {
+        Pos { x: self.x + k * m.dx, y: self.y + k * m.dy, z: self.z + k * m.dz }
     }
 
     fn is_close(&self, other: &Pos) -> bool {
         self.x.abs_diff(other.x) <= 1 &&
-            self.y.abs_diff(other.y) <= 1
+            self.y.abs_diff(other.y) <= 1 &&
+            self.z.abs_diff(other.z) <= 1
     }
 
     fn catch_up(&self, other: &Pos) -> Pos {
         if self.is_close(other) {
-            self.clone()
+            *self
         } else {
-            let diff_x = other.x - self.x;
-            let diff_y = other.y - self.y;
-            let to_add_x = if diff_x != 0 { diff_x / diff_x.abs() } else { 0 };
-            let to_add_y = if diff_y != 0 { diff_y / diff_y.abs() } else { 0 };
-            Pos { x: self.x + to_add_x, y: self.y + to_add_y }
+            Pos {
+                x: self.x + (other.x - self.x).signum(),
+                y: self.y + (other.y - self.y).signum(),
+                z: self.z + (other.z - self.z).signum(),
+            }
+        }
+    }
+}
+
+// Direction and number of steps of an input line
+#[derive(Debug)]
+struct Command {
+    dir: Move,
+    count: u32,
+}
+
+const TILE: i32 = 64;
+
+// Set of positions, stored as 64x64 bitmap tiles of the x-y plane, created
+// on demand. Memory grows with the visited cells, not with their bounding
+// box.
+struct VisitedMap {
+    // Tile (x / 64, y / 64, z) -> one row of 64 bits per y
+    tiles: HashMap<(i32, i32, i32), [u64; TILE as usize]>,
+    len: usize,
+}
+
+impl VisitedMap {
+    fn new() -> Self {
+        Self { tiles: HashMap::new(), len: 0 }
+    }
+
+    fn tile_key(p: &Pos) -> (i32, i32, i32) {
+        (p.x.div_euclid(TILE), p.y.div_euclid(TILE), p.z)
+    }
+
+    fn insert(&mut self, p: Pos) {
+        self.insert_segment(p, &Move { dx: 0, dy: 0, dz: 0 }, 1);
+    }
+
+    // Inserts the count positions start, start + m, start + 2m... Cells
+    // falling in the same tile are set together, and a horizontal run within
+    // a tile row with a single mask.
+    fn insert_segment(&mut self, start: Pos, m: &Move, count: u32) {
+        let mut p = start;
+        let mut left = count as i64;
+        while left > 0 {
+            let key = VisitedMap::tile_key(&p);
+            let tile = self.tiles.entry(key).or_insert([0; TILE as usize]);
+            while left > 0 && VisitedMap::tile_key(&p) == key {
+                let row = &mut tile[p.y.rem_euclid(TILE) as usize];
+                let col = p.x.rem_euclid(TILE);
+                // Cells of the run inside this tile row
+                let run = match m.dx {
+                    _ if m.dy != 0 || m.dz != 0 => 1,
+                    1 => ((TILE - col) as i64).min(left),
+                    -1 => ((col + 1) as i64).min(left),
+                    _ => 1,
+                };
+                let first = if m.dx < 0 { col - run as i32 + 1 } else { col };
+                let mask = if run == TILE as i64 { u64::MAX } else { ((1u64 << run) - 1) << first };
+                self.len += (mask & !*row).count_ones() as usize;
+                *row |= mask;
+                p = p.shift(m, run as i32);
+                left -= run;
+            }
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Pos> + '_ {
+        self.tiles.iter().flat_map(|(&(tx, ty, z), tile)| {
+            tile.iter().enumerate()
+                .filter(|(_, &row)| row != 0)
+                .flat_map(move |(r, &row)| {
+                    (0..TILE).filter(move |bit| row & (1 << bit) != 0)
+                        .map(move |bit| Pos { x: tx * TILE + bit, y: ty * TILE + r as i32, z })
+                })
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+// Steps of a trace, in order
+enum Segment {
+    // Steps whose knot moves are stored in Trace::moves
+    Steps(u32),
+    // Steps moving every knot by the same offset
+    Rigid(Move, u32),
+}
+
+// Position of every knot after each step, step 0 being the initial state.
+// Only the knot moves are stored, one byte per knot and step, and rigid
+// translations take a single segment whatever their length. Positions are
+// expanded when writing the trace.
+struct Trace {
+    start: Vec<Pos>,
+    // Encoded moves of each knot, step after step, head first
+    moves: Vec<u8>,
+    segments: Vec<Segment>,
+}
+
+impl Trace {
+    fn new(knots: &[Pos]) -> Self {
+        Self { start: knots.to_vec(), moves: Vec::new(), segments: Vec::new() }
+    }
+
+    fn record_step(&mut self, before: &[Pos], after: &[Pos]) {
+        self.moves.extend(before.iter().zip(after).map(|(b, a)| Move::encode(b, a)));
+        match self.segments.last_mut() {
+            Some(Segment::Steps(count)) => *count += 1,
+            _ => self.segments.push(Segment::Steps(1)),
+        }
+    }
+
+    fn record_rigid(&mut self, m: &Move, count: u32) {
+        if count > 0 {
+            self.segments.push(Segment::Rigid(*m, count));
+        }
+    }
+
+    // Calls f with the index and the knot positions of every step
+    fn for_each_step(&self, mut f: impl FnMut(usize, &[Pos]) -> io::Result<()>) -> io::Result<()> {
+        let mut knots = self.start.clone();
+        let mut step = 0;
+        f(step, &knots)?;
+        let mut moves = self.moves.chunks(knots.len());
+        for segment in &self.segments {
+            let count = match segment { Segment::Steps(count) | Segment::Rigid(_, count) => *count };
+            for _ in 0..count {
+                match segment {
+                    Segment::Steps(_) => knots.iter_mut().zip(moves.next().unwrap())
+                        .for_each(|(p, &code)| *p = p.apply_move(&Move::decode(code))),
+                    Segment::Rigid(m, _) => knots.iter_mut().for_each(|p| *p = p.apply_move(m)),
+                }
+                step += 1;
+                f(step, &knots)?;
+            }
         }
+        Ok(())
+    }
+
+    fn write_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "step,knot,x,y,z")?;
+        self.for_each_step(|step, knots| {
+            for (knot, p) in knots.iter().enumerate() {
+                writeln!(w, "{},{},{},{},{}", step, knot, p.x, p.y, p.z)?;
+            }
+            Ok(())
+        })
+    }
+
+    // Every step is a list of [x,y,z] triples, head first
+    fn write_json(&self, w: &mut impl Write) -> io::Result<()> {
+        write!(w, "{{\"knots\":{},\"steps\":[", self.start.len())?;
+        self.for_each_step(|step, knots| {
+            let knots: Vec<String> = knots.iter().map(|p| format!("[{},{},{}]", p.x, p.y, p.z)).collect();
+            write!(w, "{}[{}]", if step == 0 { "" } else { "," }, knots.join(","))
+        })?;
+        writeln!(w, "]}}")
     }
 }
 
 struct Rope {
     knots: Vec<Pos>,
-    len: usize
+    len: usize,
+    // First knot whose positions are tracked: the head, or just the tail
+    first_tracked: usize,
+    // Positions visited by each tracked knot
+    visited: Vec<VisitedMap>,
+    trace: Option<Trace>,
 }
 
 impl Rope {
-    fn new(len: usize) -> Self {
-        Self {
-            knots: vec![Pos::default(); len],
-            len
-        }
+    // Tracks the positions of every knot if all_knots, otherwise of the tail only
+    fn new(len: usize, all_knots: bool) -> Self {
+        let knots = vec![Pos::default(); len];
+        let first_tracked = if all_knots { 0 } else { len - 1 };
+        let visited = knots[first_tracked..].iter().map(|&knot| {
+            let mut map = VisitedMap::new();
+            map.insert(knot);
+            map
+        }).collect();
+        Self { knots, len, first_tracked, visited, trace: None }
+    }
+
+    // Also records every step into a trace
+    fn with_trace(len: usize, all_knots: bool) -> Self {
+        let mut rope = Rope::new(len, all_knots);
+        rope.trace = Some(Trace::new(&rope.knots));
+        rope
     }
 
     fn apply_move(&mut self, m: &Move) {
@@ -60,45 +282,190 @@ impl Rope {
         for i in 1..self.len {
             self.knots[i] = self.knots[i].catch_up(&self.knots[i-1]);
         }
+        self.knots[self.first_tracked..].iter().zip(self.visited.iter_mut()).for_each(|(&knot, visited)| {
+            visited.insert(knot);
+        });
+    }
+
+    fn get_tail_visited(&self) -> &VisitedMap {
+        self.visited.last().unwrap()
+    }
+
+    // None if the knot does not exist or is not tracked
+    fn get_visited(&self, knot: usize) -> Option<&VisitedMap> {
+        knot.checked_sub(self.first_tracked).and_then(|idx| self.visited.get(idx))
     }
 
-    fn get_tail_position(&self) -> Pos {
-        self.knots[self.len-1].clone()
+    // Applies every step of cmd. As soon as a step moves every knot by the
+    // same offset, the rope has become rigid and the remaining steps just
+    // translate it.
+    fn apply_command(&mut self, cmd: &Command) {
+        let mut remaining = cmd.count;
+        while remaining > 0 {
+            let before = self.knots.clone();
+            self.apply_move(&cmd.dir);
+            remaining -= 1;
+            if let Some(trace) = &mut self.trace {
+                trace.record_step(&before, &self.knots);
+            }
+
+            let rigid = self.knots.iter().zip(&before)
+                .all(|(after, before)| *after == before.apply_move(&cmd.dir));
+            if rigid {
+                break;
+            }
+        }
+
+        if let Some(trace) = &mut self.trace {
+            trace.record_rigid(&cmd.dir, remaining);
+        }
+        let shift = remaining as i32;
+        let tracked = self.first_tracked;
+        self.knots.iter_mut().enumerate().for_each(|(idx, knot)| {
+            if let Some(visited) = idx.checked_sub(tracked).map(|idx| &mut self.visited[idx]) {
+                visited.insert_segment(knot.shift(&cmd.dir, 1), &cmd.dir, remaining);
+            }
+            *knot = knot.shift(&cmd.dir, shift);
+        });
     }
 }
 
-fn parse_input() -> Vec<Move> {
+fn parse_input() -> Vec<Command> {
     let f = File::open("input/input.txt").unwrap();
     let lines = io::BufReader::new(f).lines();
-    lines.flat_map(|line| {
+    lines.map(|line| {
         let line = line.unwrap();
-        let dist = &line[2..].parse::<u32>().unwrap();
-        (0..*dist).map(move |_| {
-            match &line[0..1] {
-                "R" => R,
-                "L" => L,
-                "U" => U,
-                "D" => D,
-                _ => panic!("Unknown command")
-            }
-        }).into_iter()
+        let (dir, count) = line.split_once(' ').expect("Malformed command");
+        Command { dir: Move::parse(dir), count: count.parse::<u32>().unwrap() }
     }).collect()
 }
 
-fn compute_visited(rope_len: usize, input: &Vec<Move>) -> usize {
-    let mut rope = Rope::new(rope_len);
-    let mut visited = HashSet::from([rope.get_tail_position()]);
-    input.iter().for_each(|m| {
-        rope.apply_move(m);
-        visited.insert(rope.get_tail_position());
+fn simulate<'a>(rope_len: usize, input: impl IntoIterator<Item = &'a Command>) -> Rope {
+    let mut rope = Rope::new(rope_len, false);
+    input.into_iter().for_each(|cmd| rope.apply_command(cmd));
+    rope
+}
+
+fn compute_visited<'a>(rope_len: usize, input: impl IntoIterator<Item = &'a Command>) -> usize {
+    simulate(rope_len, input).get_tail_visited().len()
+}
+
+fn print_knots(rope: &Rope) {
+    rope.visited.iter().enumerate().for_each(|(idx, visited)| {
+        let label = if idx == 0 { "head".to_string() } else { format!("knot {idx}") };
+        println!("  {label}: {} position(s)", visited.len());
     });
-    visited.len()
 }
 
+// Draws marks on the x-y plane like the puzzle's illustrations, with y
+// growing upwards. When several marks share a cell, the first one wins.
+fn render(marks: &[(Pos, char)]) -> Vec<String> {
+    let min_x = marks.iter().map(|(p, _)| p.x).min().unwrap();
+    let max_x = marks.iter().map(|(p, _)| p.x).max().unwrap();
+    let min_y = marks.iter().map(|(p, _)| p.y).min().unwrap();
+    let max_y = marks.iter().map(|(p, _)| p.y).max().unwrap();
+    let mut grid = vec![vec!['.'; (max_x - min_x + 1) as usize]; (max_y - min_y + 1) as usize];
+    marks.iter().rev().for_each(|(p, c)| grid[(max_y - p.y) as usize][(p.x - min_x) as usize] = *c);
+    grid.into_iter().map(String::from_iter).collect()
+}
+
+// Cells visited by a knot, with the start marked s
+fn render_visited(visited: &VisitedMap) -> Vec<String> {
+    let mut marks = vec![(Pos::default(), 's')];
+    marks.extend(visited.iter().map(|p| (p, '#')));
+    render(&marks)
+}
+
+// H for the head, then T for the tail of a two-knot rope or the knot index
+fn knot_label(idx: usize, len: usize) -> char {
+    match idx {
+        0 => 'H',
+        _ if len == 2 => 'T',
+        _ => char::from_digit(idx as u32 % 36, 36).unwrap(),
+    }
+}
+
+fn render_rope(rope: &Rope) -> Vec<String> {
+    let mut marks: Vec<(Pos, char)> = rope.knots.iter().enumerate()
+        .map(|(idx, &p)| (p, knot_label(idx, rope.len)))
+        .collect();
+    marks.push((Pos::default(), 's'));
+    render(&marks)
+}
+
+fn write_trace(trace: &Trace, format: &str, path: &str) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    match format {
+        "csv" => trace.write_csv(&mut w)?,
+        "json" => trace.write_json(&mut w)?,
+        _ => panic!("Unknown trace format {format}"),
+    }
+    w.flush()
+}
+
+// Usage: day_9 [--rope N] [--knots] [--trace csv|json FILE] [--map K] [--states]
+// Directions combine R/L, U/D and F/B (e.g. UR, DLF) for diagonal and 3D moves
+// --knots reports the positions visited by every knot, not just the tail
+// --trace writes the position of every knot after each step
+// --map draws the cells visited by knot K (0 is the head)
+// --states draws the rope after each command
+// Debugging options use the rope of --rope, or the 10 knots rope of part 2.
+// Drawings project 3D ropes on the x-y plane.
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let arg_value = |name: &str| {
+        args.iter().position(|a| a == name).map(|idx| args.get(idx + 1).expect("Missing argument value"))
+    };
+    let flag = |name: &str| args.iter().any(|a| a == name);
     let input = parse_input();
-    // Part 1
-    println!("Visited positions (rope len=2): {}", compute_visited(2, &input));
-    // Part 2
-    println!("Visited positions (rope len=10): {}", compute_visited(10, &input));
+    let rope_len = arg_value("--rope")
+        .map(|len| len.parse::<usize>().ok().filter(|&len| len > 0).expect("Invalid rope length"));
+
+    match rope_len {
+        Some(len) => println!("Visited positions (rope len={len}): {}", compute_visited(len, &input)),
+        None => {
+            // Part 1
+            println!("Visited positions (rope len=2): {}", compute_visited(2, &input));
+            // Part 2
+            println!("Visited positions (rope len=10): {}", compute_visited(10, &input));
+        }
+    }
+
+    let trace = args.iter().position(|a| a == "--trace")
+        .map(|idx| (args.get(idx + 1).expect("Missing trace format"), args.get(idx + 2).expect("Missing trace file")));
+    let map = arg_value("--map").map(|k| k.parse::<usize>().expect("Invalid knot"));
+    if !flag("--knots") && trace.is_none() && map.is_none() && !flag("--states") {
+        return;
+    }
+
+    let len = rope_len.unwrap_or(10);
+    // Only --knots and --map need the positions of every knot
+    let all_knots = flag("--knots") || map.is_some();
+    let mut rope = if trace.is_some() { Rope::with_trace(len, all_knots) } else { Rope::new(len, all_knots) };
+    if flag("--states") {
+        println!("== Initial State ==");
+        render_rope(&rope).iter().for_each(|row| println!("{row}"));
+    }
+    for cmd in &input {
+        rope.apply_command(cmd);
+        if flag("--states") {
+            println!();
+            println!("== {} {} ==", cmd.dir, cmd.count);
+            render_rope(&rope).iter().for_each(|row| println!("{row}"));
+        }
+    }
+
+    if flag("--knots") {
+        print_knots(&rope);
+    }
+    if let Some(k) = map {
+        let visited = rope.get_visited(k).expect("No such knot");
+        println!("Visited by knot {k}:");
+        render_visited(visited).iter().for_each(|row| println!("{row}"));
+    }
+    if let (Some((format, path)), Some(t)) = (trace, &rope.trace) {
+        if let Err(e) = write_trace(t, format, path) {
+            eprintln!("Error: {e}");
+        }
+    }
 }
@@ -0,0 +1,444 @@
+use std::cmp::{max, min};
+use std::fmt::{Display, Formatter};
+
+// Non-empty set of consecutive integers, stored as the closed range [start, end]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Interval {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Interval {
+    // [start, end]; panics if end < start
+    pub fn closed(start: i64, end: i64) -> Self {
+        assert!(start <= end, "Empty interval [{}, {}]", start, end);
+        Self { start, end }
+    }
+
+    // [start, end); None if the range is empty
+    pub fn half_open(start: i64, end: i64) -> Option<Self> {
+        if start < end {
+            Some(Self { start, end: end - 1 })
+        } else {
+            None
+        }
+    }
+
+    // Intervals are never empty; u128 since [i64::MIN, i64::MAX] holds 2^64 integers
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u128 {
+        self.start.abs_diff(self.end) as u128 + 1
+    }
+
+    pub fn contains(&self, x: i64) -> bool {
+        self.start <= x && x <= self.end
+    }
+
+    pub fn contains_interval(&self, other: &Interval) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    // True if either interval contains the other
+    pub fn nested(&self, other: &Interval) -> bool {
+        self.contains_interval(other) || other.contains_interval(self)
+    }
+
+    pub fn overlaps(&self, other: &Interval) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    // True if the two intervals overlap or touch, so their union is an interval
+    pub fn mergeable(&self, other: &Interval) -> bool {
+        self.start <= other.end.saturating_add(1) && other.start <= self.end.saturating_add(1)
+    }
+
+    pub fn intersection(&self, other: &Interval) -> Option<Interval> {
+        if self.overlaps(other) {
+            Some(Interval::closed(max(self.start, other.start), min(self.end, other.end)))
+        } else {
+            None
+        }
+    }
+
+    // None if the union is not a single interval
+    pub fn union(&self, other: &Interval) -> Option<Interval> {
+        if self.mergeable(other) {
+            Some(Interval::closed(min(self.start, other.start), max(self.end, other.end)))
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+// Union of intervals, kept normalized: sorted, disjoint and non-adjacent
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntervalSet {
+    intervals: Vec<Interval>,
+}
+
+impl IntervalSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, interval: Interval) {
+        // First interval that could merge with the new one
+        let first = self.intervals.partition_point(|i| i.end.saturating_add(1) < interval.start);
+        let mut merged = interval;
+        let mut last = first;
+        while last < self.intervals.len() && self.intervals[last].mergeable(&merged) {
+            merged = merged.union(&self.intervals[last]).unwrap();
+            last += 1;
+        }
+        self.intervals.splice(first..last, [merged]);
+    }
+
+    pub fn intervals(&self) -> &[Interval] {
+        &self.intervals
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    pub fn contains(&self, x: i64) -> bool {
+        let idx = self.intervals.partition_point(|i| i.end < x);
+        idx < self.intervals.len() && self.intervals[idx].contains(x)
+    }
+
+    // Number of integers covered by the set
+    pub fn coverage(&self) -> u128 {
+        self.intervals.iter().map(|i| i.len()).sum()
+    }
+
+    // Holes between the first and the last interval of the set
+    pub fn gaps(&self) -> Vec<Interval> {
+        self.intervals.windows(2)
+            .map(|w| Interval::closed(w[0].end + 1, w[1].start - 1))
+            .collect()
+    }
+
+    // Parts of bounds not covered by the set
+    pub fn gaps_within(&self, bounds: Interval) -> Vec<Interval> {
+        let mut res = Vec::new();
+        let mut next = bounds.start;
+        for i in self.intervals.iter().filter(|i| i.overlaps(&bounds)) {
+            if i.start > next {
+                res.push(Interval::closed(next, i.start - 1));
+            }
+            if i.end >= bounds.end {
+                return res;
+            }
+            next = i.end + 1;
+        }
+        res.push(Interval::closed(next, bounds.end));
+        res
+    }
+
+    pub fn intersection(&self, other: &IntervalSet) -> IntervalSet {
+        let mut res = IntervalSet::new();
+        let (mut a, mut b) = (0, 0);
+        while a < self.intervals.len() && b < other.intervals.len() {
+            let (ia, ib) = (&self.intervals[a], &other.intervals[b]);
+            if let Some(i) = ia.intersection(ib) {
+                res.intervals.push(i);
+            }
+            if ia.end < ib.end { a += 1; } else { b += 1; }
+        }
+        res
+    }
+
+    pub fn union(&self, other: &IntervalSet) -> IntervalSet {
+        let mut res = self.clone();
+        other.intervals.iter().for_each(|&i| res.insert(i));
+        res
+    }
+}
+
+impl FromIterator<Interval> for IntervalSet {
+    fn from_iter<T: IntoIterator<Item = Interval>>(iter: T) -> Self {
+        let mut intervals: Vec<Interval> = iter.into_iter().collect();
+        intervals.sort();
+
+        let mut res: Vec<Interval> = Vec::with_capacity(intervals.len());
+        for interval in intervals {
+            match res.last_mut() {
+                Some(last) if last.mergeable(&interval) => *last = last.union(&interval).unwrap(),
+                _ => res.push(interval),
+            }
+        }
+        Self { intervals: res }
+    }
+}
+
+// Static interval tree: entries sorted by interval, seen as an implicit
+// balanced binary tree where every node knows the max end of its subtree
+#[derive(Clone, Debug)]
+pub struct IntervalTree<T> {
+    entries: Vec<(Interval, T)>,
+    max_end: Vec<i64>,
+}
+
+impl<T> IntervalTree<T> {
+    pub fn new(mut entries: Vec<(Interval, T)>) -> Self {
+        entries.sort_by_key(|(interval, _)| *interval);
+        let mut tree = Self { max_end: vec![i64::MIN; entries.len()], entries };
+        tree.build(0, tree.entries.len());
+        tree
+    }
+
+    fn build(&mut self, lo: usize, hi: usize) -> i64 {
+        if lo >= hi {
+            return i64::MIN;
+        }
+        let mid = (lo + hi) / 2;
+        let left = self.build(lo, mid);
+        let right = self.build(mid + 1, hi);
+        self.max_end[mid] = max(self.entries[mid].0.end, max(left, right));
+        self.max_end[mid]
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Entries whose interval contains x
+    pub fn covering(&self, x: i64) -> Vec<&(Interval, T)> {
+        self.overlapping(Interval::closed(x, x))
+    }
+
+    // Entries whose interval overlaps query, sorted by interval
+    pub fn overlapping(&self, query: Interval) -> Vec<&(Interval, T)> {
+        let mut res = Vec::new();
+        self.collect(0, self.entries.len(), &query, &mut res);
+        res
+    }
+
+    fn collect<'a>(&'a self, lo: usize, hi: usize, query: &Interval, res: &mut Vec<&'a (Interval, T)>) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        if self.max_end[mid] < query.start {
+            return;
+        }
+        self.collect(lo, mid, query, res);
+        // Everything from mid onwards starts too late
+        if self.entries[mid].0.start > query.end {
+            return;
+        }
+        if self.entries[mid].0.overlaps(query) {
+            res.push(&self.entries[mid]);
+        }
+        self.collect(mid + 1, hi, query, res);
+    }
+}
+
+impl<T> FromIterator<(Interval, T)> for IntervalTree<T> {
+    fn from_iter<I: IntoIterator<Item = (Interval, T)>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(intervals: &[(i64, i64)]) -> IntervalSet {
+        intervals.iter().map(|&(s, e)| Interval::closed(s, e)).collect()
+    }
+
+    fn pairs(set: &IntervalSet) -> Vec<(i64, i64)> {
+        set.intervals().iter().map(|i| (i.start, i.end)).collect()
+    }
+
+    #[test]
+    fn single_element() {
+        let i = Interval::closed(5, 5);
+        assert_eq!(i.len(), 1);
+        assert!(i.contains(5));
+        assert!(!i.contains(4) && !i.contains(6));
+
+        let mut s = IntervalSet::new();
+        s.insert(i);
+        assert_eq!(pairs(&s), [(5, 5)]);
+        assert_eq!(s.coverage(), 1);
+        assert_eq!(s, set(&[(5, 5)]));
+    }
+
+    #[test]
+    fn full_range_len() {
+        let full = Interval::closed(i64::MIN, i64::MAX);
+        assert_eq!(full.len(), 1u128 << 64);
+        assert_eq!(set(&[(i64::MIN, -1), (0, i64::MAX)]).coverage(), 1u128 << 64);
+    }
+
+    #[test]
+    fn adjacent_and_overlapping_merges() {
+        // Adjacent intervals merge, separated ones do not
+        assert_eq!(pairs(&set(&[(1, 3), (4, 6)])), [(1, 6)]);
+        assert_eq!(pairs(&set(&[(1, 3), (5, 6)])), [(1, 3), (5, 6)]);
+        // Overlapping and nested intervals merge
+        assert_eq!(pairs(&set(&[(1, 4), (3, 6)])), [(1, 6)]);
+        assert_eq!(pairs(&set(&[(1, 10), (3, 6)])), [(1, 10)]);
+
+        let mut s = set(&[(1, 3)]);
+        s.insert(Interval::closed(4, 6));
+        assert_eq!(pairs(&s), [(1, 6)]);
+        s.insert(Interval::closed(8, 9));
+        assert_eq!(pairs(&s), [(1, 6), (8, 9)]);
+        s.insert(Interval::closed(i64::MIN, 0));
+        assert_eq!(pairs(&s), [(i64::MIN, 6), (8, 9)]);
+    }
+
+    #[test]
+    fn insert_bridging_several() {
+        let mut s = set(&[(0, 1), (4, 5), (8, 9), (12, 13), (20, 21)]);
+        s.insert(Interval::closed(2, 11));
+        assert_eq!(pairs(&s), [(0, 13), (20, 21)]);
+        s.insert(Interval::closed(-5, 30));
+        assert_eq!(pairs(&s), [(-5, 30)]);
+        assert_eq!(s.coverage(), 36);
+    }
+
+    #[test]
+    fn gaps() {
+        let s = set(&[(0, 2), (5, 6), (10, 10)]);
+        assert_eq!(s.gaps(), [Interval::closed(3, 4), Interval::closed(7, 9)]);
+        assert_eq!(s.gaps_within(Interval::closed(-1, 11)), [
+            Interval::closed(-1, -1), Interval::closed(3, 4), Interval::closed(7, 9), Interval::closed(11, 11),
+        ]);
+        assert_eq!(s.gaps_within(Interval::closed(1, 5)), [Interval::closed(3, 4)]);
+    }
+
+    #[test]
+    fn gaps_within_outside_bounds() {
+        let s = set(&[(0, 2), (5, 6)]);
+        assert_eq!(s.gaps_within(Interval::closed(-10, -5)), [Interval::closed(-10, -5)]);
+        assert_eq!(s.gaps_within(Interval::closed(20, 30)), [Interval::closed(20, 30)]);
+        assert_eq!(s.gaps_within(Interval::closed(3, 4)), [Interval::closed(3, 4)]);
+        assert_eq!(IntervalSet::new().gaps_within(Interval::closed(1, 2)), [Interval::closed(1, 2)]);
+        assert!(s.gaps_within(Interval::closed(5, 6)).is_empty());
+    }
+
+    #[test]
+    fn set_intersection() {
+        let a = set(&[(0, 5), (10, 15), (20, 25)]);
+        let b = set(&[(3, 12), (14, 21), (30, 40)]);
+        assert_eq!(pairs(&a.intersection(&b)), [(3, 5), (10, 12), (14, 15), (20, 21)]);
+        assert_eq!(a.intersection(&b), b.intersection(&a));
+        assert!(a.intersection(&set(&[(6, 9), (26, 29)])).is_empty());
+        assert!(a.intersection(&IntervalSet::new()).is_empty());
+        assert_eq!(a.intersection(&a), a);
+    }
+
+    #[test]
+    fn set_union() {
+        let a = set(&[(0, 5), (20, 25)]);
+        let b = set(&[(6, 8), (15, 19), (40, 41)]);
+        assert_eq!(pairs(&a.union(&b)), [(0, 8), (15, 25), (40, 41)]);
+    }
+
+    #[test]
+    fn half_open_ranges() {
+        assert_eq!(Interval::half_open(3, 3), None);
+        assert_eq!(Interval::half_open(4, 3), None);
+        assert_eq!(Interval::half_open(i64::MIN, i64::MIN), None);
+        assert_eq!(Interval::half_open(3, 4), Some(Interval::closed(3, 3)));
+        assert_eq!(Interval::half_open(-2, 3).map(|i| i.len()), Some(5));
+    }
+
+    #[test]
+    #[should_panic]
+    fn closed_empty_range() {
+        Interval::closed(3, 2);
+    }
+
+    // Deterministic xorshift, enough for generating test cases
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, bound: i64) -> i64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as i64
+        }
+    }
+
+    // Random intervals within [0, 100), with explicit duplicates and nested ones
+    fn random_entries(rng: &mut Rng, count: usize) -> Vec<(Interval, usize)> {
+        let mut res: Vec<Interval> = Vec::new();
+        for _ in 0..count {
+            let interval = match rng.next(4) {
+                0 if !res.is_empty() => res[rng.next(res.len() as i64) as usize],
+                1 if !res.is_empty() => {
+                    let outer = res[rng.next(res.len() as i64) as usize];
+                    let start = outer.start + rng.next(outer.len() as i64);
+                    Interval::closed(start, start + rng.next(outer.end - start + 1))
+                }
+                _ => {
+                    let start = rng.next(100);
+                    Interval::closed(start, start + rng.next(20))
+                }
+            };
+            res.push(interval);
+        }
+        res.into_iter().enumerate().map(|(id, i)| (i, id)).collect()
+    }
+
+    fn brute_force(entries: &[(Interval, usize)], query: &Interval) -> Vec<(Interval, usize)> {
+        let mut res: Vec<_> = entries.iter().filter(|(i, _)| i.overlaps(query)).copied().collect();
+        res.sort();
+        res
+    }
+
+    fn sorted(found: Vec<&(Interval, usize)>) -> Vec<(Interval, usize)> {
+        let mut res: Vec<_> = found.into_iter().copied().collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn tree_matches_brute_force() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for count in (0..40).chain([100, 257]) {
+            let entries = random_entries(&mut rng, count);
+            let tree: IntervalTree<usize> = entries.iter().copied().collect();
+            assert_eq!(tree.len(), count);
+            for x in -2..125 {
+                let query = Interval::closed(x, x);
+                assert_eq!(sorted(tree.covering(x)), brute_force(&entries, &query), "covering {x}, {count} entries");
+            }
+            for _ in 0..50 {
+                let start = rng.next(130) - 5;
+                let query = Interval::closed(start, start + rng.next(30));
+                let found = tree.overlapping(query);
+                assert!(found.windows(2).all(|w| w[0].0 <= w[1].0), "results are not sorted");
+                assert_eq!(sorted(found), brute_force(&entries, &query), "overlapping {query}, {count} entries");
+            }
+        }
+    }
+
+    #[test]
+    fn tree_duplicates_and_extremes() {
+        let full = Interval::closed(i64::MIN, i64::MAX);
+        let tree: IntervalTree<usize> = [(Interval::closed(1, 2), 0), (Interval::closed(1, 2), 1), (full, 2), (Interval::closed(2, 2), 3)]
+            .into_iter().collect();
+        assert_eq!(sorted(tree.covering(2)).len(), 4);
+        assert_eq!(sorted(tree.covering(i64::MIN)), [(full, 2)]);
+        assert_eq!(sorted(tree.covering(i64::MAX)), [(full, 2)]);
+        assert_eq!(sorted(tree.overlapping(Interval::closed(0, 1))).len(), 3);
+        assert!(IntervalTree::<usize>::new(Vec::new()).covering(0).is_empty());
+    }
+}